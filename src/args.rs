@@ -1,56 +1,254 @@
 use std::path::PathBuf;
 
-use clap::{Parser, ValueEnum};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::bump_labels::BumpLabels;
+use crate::forge::ForgeHosts;
+use crate::output::OutputFormat;
+use crate::signing::SignMode;
+use crate::version_files::VersionFile;
+
+const BUMP_HELP: &str = " by patch (e.g. v1.0.0 -> v1.0.1)\n by minor (e.g. v1.0.0 -> v1.1.0)\n by major (e.g. v1.0.0 -> v2.0.0)\n auto (inferred from PR labels with --use-pr, or Conventional Commits, default with --use-pr)\n release (e.g. v1.1.0-rc.2 -> v1.1.0)\n";
 
 #[derive(Parser, Debug)]
 #[command(name = "tagge_rs")]
 #[command(about = "Semantic versioning and tagging CLI tool for Git repos", long_about = None)]
+#[command(
+    after_help = "Most settings of .tagge.toml can also be set with TAGGE_<NAME> environment variables (e.g. TAGGE_USE_PR=true), and switches such as --push=false turn off a configured true. `tagge_rs config show` prints where each one came from"
+)]
 pub struct CliArgs {
-    #[arg(
-        value_enum,
-        help = " by patch (e.g. v1.0.0 -> v1.0.1)\n by minor (e.g. v1.0.0 -> v1.1.0)\n by major (e.g. v1.0.0 -> v2.0.0)\n"
-    )]
-    pub bump: Option<VersionBump>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    /// Override the auto-generated tag
-    #[arg(short, long)]
-    pub tag: Option<String>,
+    // Legacy form of `bump <BUMP>`
+    #[arg(value_enum, help = BUMP_HELP)]
+    pub bump: Option<VersionBump>,
 
-    /// Create the first tag. Defaults to v0.1.0. Use `--tag` to override.
+    /// Legacy form of `init`
     #[arg(long)]
     pub init: bool,
 
-    /// Extra suffix for the tag
-    #[arg(long)]
-    pub suffix: Option<String>,
+    #[command(flatten, next_help_heading = "Tagging options")]
+    pub tag_args: TagArgs,
 
     /// Use commit SHA hash in changelog
-    #[arg(short = 's', long)]
+    #[arg(short = 's', long, global = true, num_args = 0..=1, require_equals = true, default_value_t = false, hide_default_value = true, default_missing_value = "true", action = ArgAction::Set, value_name = "BOOL")]
     pub use_sha: bool,
 
     /// Use PR tags in changelog
-    #[arg(short = 'r', long)]
+    #[arg(short = 'r', long, global = true, num_args = 0..=1, require_equals = true, default_value_t = false, hide_default_value = true, default_missing_value = "true", action = ArgAction::Set, value_name = "BOOL")]
     pub use_pr: bool,
 
-    /// Dry run, only print the tag command, do not create a tag
-    #[arg(short, long)]
-    pub dry_run: bool,
+    /// With --use-pr, use PR titles grouped by label as changelog entries,
+    /// one entry per PR with the author as @handle
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_value_t = false, hide_default_value = true, default_missing_value = "true", action = ArgAction::Set, value_name = "BOOL")]
+    pub pr_titles: bool,
 
     /// Path to the Git repository (default: current directory)
-    #[arg(short, long, default_value_t = String::from("."))]
+    #[arg(short, long, default_value_t = String::from("."), global = true)]
     pub path: String,
 
     /// Skip fetching git tags
-    #[arg(long)]
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_value_t = false, hide_default_value = true, default_missing_value = "true", action = ArgAction::Set, value_name = "BOOL")]
     pub no_fetch: bool,
 
-    /// Github token for fetching information about pull requests / commits
-    #[arg(long)]
-    pub gh_token: Option<String>,
+    /// SSH private key for fetching and pushing, tried after ssh-agent
+    /// and before the keys in ~/.ssh
+    #[arg(long, value_name = "PATH", global = true)]
+    pub ssh_key: Option<PathBuf>,
+
+    /// Branches that tags are expected to be created on
+    #[arg(long, value_delimiter = ',', global = true, default_values_t = [String::from("main"), String::from("master")])]
+    pub branches: Vec<String>,
+
+    /// Git remote to fetch tags from, push to and resolve the forge from
+    /// (default: the remote the current branch tracks, else origin)
+    #[arg(long, global = true)]
+    pub remote: Option<String>,
+
+    /// Forge API token for pull requests, releases and fetching or pushing over HTTPS
+    /// (defaults to GH_TOKEN, GITLAB_TOKEN, GITEA_TOKEN or FORGEJO_TOKEN depending on the forge)
+    #[arg(long, alias = "gh-token", global = true)]
+    pub token: Option<String>,
 
     /// Add additional debug logging
-    #[arg(long)]
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_value_t = false, hide_default_value = true, default_missing_value = "true", action = ArgAction::Set, value_name = "BOOL")]
     pub debug: bool,
+
+    /// Answer yes to every question, e.g. to confirm the branch, without reading stdin
+    #[arg(short = 'y', long, env = "TAGGE_YES", global = true)]
+    pub yes: bool,
+
+    /// Never read stdin, questions take their safe answer: the branch is confirmed,
+    /// a tag that could not be pushed is kept
+    #[arg(long, env = "TAGGE_NO_INPUT", conflicts_with = "yes", global = true)]
+    pub no_input: bool,
+
+    /// Write new_version, previous_version, tag, changelog and released to a dotenv file,
+    /// for GitLab CI `artifacts:reports:dotenv`
+    #[arg(long, env = "TAGGE_DOTENV", value_name = "PATH", global = true)]
+    pub dotenv: Option<PathBuf>,
+
+    /// Format of the results, json also renders errors as JSON
+    #[arg(long, env = "TAGGE_OUTPUT", value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text, global = true)]
+    pub output: OutputFormat,
+
+    /// PR labels that select the bump for `auto`, only set from the config
+    #[arg(skip)]
+    pub bump_labels: BumpLabels,
+
+    /// Forges of self-hosted domains, only set from the config
+    #[arg(skip)]
+    pub forge_hosts: ForgeHosts,
+}
+
+/// Options for creating a tag, shared by `bump`, `init` and the legacy form.
+#[derive(Args, Debug, Default)]
+pub struct TagArgs {
+    /// Override the auto-generated tag
+    #[arg(short, long)]
+    pub tag: Option<String>,
+
+    /// Pre-release channel for the new version (e.g. v1.0.0 -> v1.1.0-rc.1).
+    /// Without a bump, continues the latest pre-release (rc.1 -> rc.2, beta.3 -> rc.1)
+    #[arg(long, value_enum)]
+    pub pre: Option<PreRelease>,
+
+    /// Extra suffix for the tag
+    #[arg(long)]
+    pub suffix: Option<String>,
+
+    /// Build metadata for the tag (e.g. build.42 -> v1.0.0+build.42).
+    /// Placeholders: {sha} (short HEAD SHA), {date} (UTC, YYYYMMDD), {env:NAME}
+    #[arg(long)]
+    pub metadata: Option<String>,
+
+    /// Prepend the release to a Keep a Changelog file and commit it before tagging
+    #[arg(long, num_args = 0..=1, default_missing_value = "CHANGELOG.md", value_name = "PATH")]
+    pub changelog: Option<String>,
+
+    /// Set the new version in this file before tagging (Cargo.toml, package.json,
+    /// pyproject.toml, or any file with a `pattern` in the config). Can be repeated
+    #[arg(long = "version-file", value_name = "PATH", value_parser = VersionFile::parse_arg)]
+    pub version_files: Vec<VersionFile>,
+
+    /// Push the new tag to the remote
+    #[arg(long, num_args = 0..=1, require_equals = true, default_value_t = false, hide_default_value = true, default_missing_value = "true", action = ArgAction::Set, value_name = "BOOL")]
+    pub push: bool,
+
+    /// Push the new tag and create a release on the forge with the changelog as its body
+    #[arg(long, visible_alias = "github-release")]
+    pub create_release: bool,
+
+    /// Create the release as a draft (ignored on GitLab)
+    #[arg(long, requires = "create_release")]
+    pub draft: bool,
+
+    /// Mark the release as a pre-release (automatic for pre-release versions, ignored on GitLab)
+    #[arg(long, requires = "create_release")]
+    pub prerelease: bool,
+
+    /// Upload files matching this glob to the GitHub Release, with a generated SHA256SUMS.
+    /// Can be repeated
+    #[arg(long = "asset", value_name = "GLOB", requires = "create_release")]
+    pub assets: Vec<String>,
+
+    /// How to sign the tag (default: like `git tag -a`, from tag.gpgSign and gpg.format)
+    #[arg(long, value_enum)]
+    pub sign: Option<SignMode>,
+
+    /// Dry run, only print the tag command, do not create a tag
+    #[arg(short, long)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show the latest tag and the changes since (default)
+    Show(RangeArgs),
+    /// Create a tag for the next version
+    Bump(BumpArgs),
+    /// Print the release notes since the latest tag as Markdown
+    Changelog(RangeArgs),
+    /// List the SemVer tags, newest first
+    List(ListArgs),
+    /// Check that a tag is a signed, annotated SemVer tag matching the version files
+    Verify(VerifyArgs),
+    /// Create the first tag, v0.1.0 unless --tag is given
+    Init(TagArgs),
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct BumpArgs {
+    // Required unless --pre, --tag or --use-pr, checked in `resolve_level` after the config
+    #[arg(value_enum, help = BUMP_HELP)]
+    pub level: Option<VersionBump>,
+
+    #[command(flatten)]
+    pub tag_args: TagArgs,
+}
+
+impl BumpArgs {
+    /// Default the level to `auto` with --use-pr, so the PR labels pick the bump.
+    /// Without a level, only --pre or --tag can select the new version.
+    pub fn resolve_level(&mut self, cli_args: &CliArgs) -> Result<(), clap::Error> {
+        if self.level.is_some()
+            || cli_args.tag_args.pre.is_some()
+            || cli_args.tag_args.tag.is_some()
+        {
+            return Ok(());
+        }
+        if cli_args.use_pr {
+            self.level = Some(VersionBump::Auto);
+            return Ok(());
+        }
+        Err(CliArgs::command().error(
+            ErrorKind::MissingRequiredArgument,
+            "a bump level is required unless --pre, --tag or --use-pr is given",
+        ))
+    }
+}
+
+/// Commits to show, e.g. `--from v1.2.0 --to v1.3.0` for the notes of an old release.
+#[derive(Args, Debug, Default)]
+pub struct RangeArgs {
+    /// Tag, branch or SHA to start after (default: the latest tag before --to)
+    #[arg(long, value_name = "REV")]
+    pub from: Option<String>,
+
+    /// Tag, branch or SHA to end at (default: HEAD)
+    #[arg(long, value_name = "REV")]
+    pub to: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// Only list this many tags
+    #[arg(short = 'n', long)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Tag to verify (default: the latest SemVer tag)
+    pub tag: Option<String>,
+
+    /// Fail if the tag is not signed
+    #[arg(long)]
+    pub signed: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the effective settings and where each one came from
+    Show,
 }
 
 impl CliArgs {
@@ -76,6 +274,57 @@ impl CliArgs {
             Ok(result)
         }
     }
+
+    /// The command to run, the legacy flat form maps to `init`, `bump` or `show`.
+    /// The tagging options of `bump` and `init` are moved to `tag_args`.
+    pub fn take_command(&mut self, matches: &ArgMatches) -> Result<Command, clap::Error> {
+        // Only the global options may be given before a subcommand
+        if let Some((name, _)) = matches.subcommand()
+            && let Some(arg) = CliArgs::command().get_arguments().find(|arg| {
+                !arg.is_global_set()
+                    && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+        {
+            return Err(CliArgs::command().error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "'{}' cannot be used with the '{name}' subcommand, pass it after it",
+                    arg.get_long().map_or_else(
+                        || format!("[{}]", arg.get_id().as_str().to_uppercase()),
+                        |long| format!("--{long}")
+                    )
+                ),
+            ));
+        }
+
+        let command = match self.command.take() {
+            Some(Command::Bump(mut args)) => {
+                self.tag_args = std::mem::take(&mut args.tag_args);
+                Command::Bump(args)
+            }
+            Some(Command::Init(tag_args)) => {
+                self.tag_args = tag_args;
+                Command::Init(TagArgs::default())
+            }
+            Some(command) => command,
+            None if self.init => Command::Init(TagArgs::default()),
+            None if self.bump.is_some()
+                || self.tag_args.pre.is_some()
+                || self.tag_args.tag.is_some() =>
+            {
+                Command::Bump(BumpArgs {
+                    level: self.bump,
+                    tag_args: TagArgs::default(),
+                })
+            }
+            None => Command::Show(RangeArgs::default()),
+        };
+        Ok(command)
+    }
+
+    pub fn remote(&self) -> &str {
+        self.remote.as_deref().unwrap_or("origin")
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
@@ -83,28 +332,45 @@ pub enum VersionBump {
     Patch,
     Minor,
     Major,
+    /// Infer the bump from Conventional Commits since the latest tag
+    Auto,
+    /// Promote the latest pre-release to its final version
+    Release,
 }
 
-// /// Add a pre-release label (e.g. alpha, beta, rc)
-// #[arg(long)]
-// pub pre: Option<String>,
-
-// /// Add build metadata (e.g. +001)
-// #[arg(long)]
-// pub metadata: Option<String>,
-
-// /// Override the auto-calculated tag
-// #[arg(long)]
-// pub tag: Option<String>,
+/// Bump of the version core, as required by a commit or a PR label.
+// Variants are ordered from the smallest to the largest bump
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum CoreBump {
+    Patch,
+    Minor,
+    Major,
+}
 
-// /// Override the generated tag message
-// #[arg(long)]
-// pub message: Option<String>,
+impl From<CoreBump> for VersionBump {
+    fn from(bump: CoreBump) -> Self {
+        match bump {
+            CoreBump::Patch => Self::Patch,
+            CoreBump::Minor => Self::Minor,
+            CoreBump::Major => Self::Major,
+        }
+    }
+}
 
-// /// Git revision or tag to start changelog from
-// #[arg(long, default_value = "last-tag")]
-// pub from: String,
+// Variants are ordered by SemVer precedence
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
+pub enum PreRelease {
+    Alpha,
+    Beta,
+    Rc,
+}
 
-// /// Include PRs in the changelog
-// #[arg(long)]
-// pub pr: bool,
+impl PreRelease {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Alpha => "alpha",
+            Self::Beta => "beta",
+            Self::Rc => "rc",
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fmt::Write as FmtWrite;
+use std::path::PathBuf;
+
+/// Name of the generated checksum file uploaded next to the assets.
+pub const CHECKSUMS_NAME: &str = "SHA256SUMS";
+
+/// A file to upload to a release.
+pub struct ReleaseAsset {
+    pub name: String,
+    pub path: PathBuf,
+    pub content_type: String,
+}
+
+/// Expand the `--asset` globs into files, failing on patterns that match nothing.
+pub fn collect(patterns: &[String]) -> MietteResult<Vec<ReleaseAsset>> {
+    let mut assets: Vec<ReleaseAsset> = Vec::new();
+    for pattern in patterns {
+        let paths = glob::glob(pattern)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid asset pattern '{pattern}'"))?;
+
+        let mut matched = false;
+        for path in paths {
+            let path = path.into_diagnostic()?;
+            if !path.is_file() {
+                continue;
+            }
+            matched = true;
+
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| miette!("Invalid asset file name {}", path.display()))?
+                .to_string();
+            if name == CHECKSUMS_NAME {
+                tracing::info!("Skipping {}, checksums are generated", path.display());
+                continue;
+            }
+            if let Some(other) = assets.iter().find(|a| a.name == name) {
+                if other.path == path {
+                    continue;
+                }
+                return Err(miette!(
+                    "Assets {} and {} have the same name!",
+                    other.path.display(),
+                    path.display()
+                ));
+            }
+
+            let content_type = mime_guess::from_path(&path)
+                .first_or_octet_stream()
+                .to_string();
+            assets.push(ReleaseAsset {
+                name,
+                path,
+                content_type,
+            });
+        }
+
+        if !matched {
+            return Err(miette!("Asset pattern '{pattern}' did not match any files"));
+        }
+    }
+    Ok(assets)
+}
+
+/// Generate a `sha256sum` compatible checksum file for the assets.
+pub fn sha256sums(assets: &[ReleaseAsset]) -> MietteResult<String> {
+    let mut sums = String::new();
+    for asset in assets {
+        let data = std::fs::read(&asset.path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read asset {}", asset.path.display()))?;
+        let digest = Sha256::digest(&data);
+        writeln!(sums, "{}  {}", hex::encode(digest), asset.name).expect("Should never fail");
+    }
+    Ok(sums)
+}
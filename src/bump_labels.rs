@@ -0,0 +1,182 @@
+use crate::args::CoreBump;
+use crate::conventional::InferredBump;
+use crate::forge::PrInfo;
+use serde::Deserialize;
+
+/// PR labels that select the version bump, from `[bump-labels]` in the config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct BumpLabels {
+    pub major: Vec<String>,
+    pub minor: Vec<String>,
+    pub patch: Vec<String>,
+}
+
+impl Default for BumpLabels {
+    fn default() -> Self {
+        Self {
+            major: vec!["semver:major".to_string()],
+            minor: vec!["semver:minor".to_string()],
+            patch: vec!["semver:patch".to_string()],
+        }
+    }
+}
+
+impl BumpLabels {
+    /// The largest bump selected by any of the labels.
+    pub fn bump(&self, labels: &[String]) -> Option<CoreBump> {
+        [
+            (CoreBump::Major, &self.major),
+            (CoreBump::Minor, &self.minor),
+            (CoreBump::Patch, &self.patch),
+        ]
+        .into_iter()
+        .find(|(_, names)| labels.iter().any(|label| names.contains(label)))
+        .map(|(bump, _)| bump)
+    }
+}
+
+impl std::fmt::Display for BumpLabels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "major: {}; minor: {}; patch: {}",
+            self.major.join(", "),
+            self.minor.join(", "),
+            self.patch.join(", ")
+        )
+    }
+}
+
+/// Version bump picked from the labels of the PRs in the release.
+pub struct LabelBump<'a> {
+    /// `None` if no PR has a bump label
+    pub inferred: Option<InferredBump>,
+    /// PRs without a bump label
+    pub unlabeled: Vec<&'a PrInfo>,
+}
+
+/// Infer the version bump from the labels of every PR linked to the commits.
+pub fn infer_bump<'a>(prs: &'a [(String, Option<PrInfo>)], labels: &BumpLabels) -> LabelBump<'a> {
+    let mut seen = Vec::new();
+    let mut inferred: Option<InferredBump> = None;
+    let mut unlabeled = Vec::new();
+
+    for pr in prs.iter().filter_map(|(_, pr)| pr.as_ref()) {
+        if seen.contains(&pr.number) {
+            continue;
+        }
+        seen.push(pr.number);
+
+        let Some(pr_bump) = labels.bump(&pr.labels) else {
+            unlabeled.push(pr);
+            continue;
+        };
+        let reason = format!("{} {}", pr.reference, pr.title);
+        match &mut inferred {
+            Some(inferred) if pr_bump < inferred.bump => {}
+            Some(inferred) if pr_bump == inferred.bump => inferred.reasons.push(reason),
+            _ => {
+                inferred = Some(InferredBump {
+                    bump: pr_bump,
+                    reasons: vec![reason],
+                })
+            }
+        }
+    }
+
+    LabelBump {
+        inferred,
+        unlabeled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pr(number: u64, labels: &[&str]) -> PrInfo {
+        PrInfo {
+            number,
+            reference: format!("#{number}"),
+            title: format!("PR {number}"),
+            author: None,
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+        }
+    }
+
+    /// PRs keyed by a commit SHA, a PR can appear for several commits.
+    fn commit_prs(prs: Vec<Option<PrInfo>>) -> Vec<(String, Option<PrInfo>)> {
+        prs.into_iter()
+            .enumerate()
+            .map(|(i, pr)| (format!("{i:07}"), pr))
+            .collect()
+    }
+
+    #[test]
+    fn highest_label_wins() {
+        let prs = commit_prs(vec![
+            Some(pr(1, &["semver:patch"])),
+            Some(pr(2, &["semver:minor", "documentation"])),
+            None,
+            Some(pr(3, &["semver:minor"])),
+            // The same PR for another commit
+            Some(pr(2, &["semver:minor", "documentation"])),
+        ]);
+        let label_bump = infer_bump(&prs, &BumpLabels::default());
+        let inferred = label_bump.inferred.unwrap();
+        assert_eq!(inferred.bump, CoreBump::Minor);
+        assert_eq!(inferred.reasons, ["#2 PR 2", "#3 PR 3"]);
+        assert!(label_bump.unlabeled.is_empty());
+
+        let prs = commit_prs(vec![
+            Some(pr(1, &["semver:minor"])),
+            Some(pr(2, &["semver:patch", "semver:major"])),
+        ]);
+        let inferred = infer_bump(&prs, &BumpLabels::default()).inferred.unwrap();
+        assert_eq!(inferred.bump, CoreBump::Major);
+        assert_eq!(inferred.reasons, ["#2 PR 2"]);
+    }
+
+    #[test]
+    fn custom_label_names() {
+        let labels = BumpLabels {
+            major: vec!["breaking".to_string()],
+            minor: vec!["feature".to_string(), "enhancement".to_string()],
+            patch: vec!["bug".to_string()],
+        };
+        let prs = commit_prs(vec![
+            Some(pr(1, &["bug"])),
+            Some(pr(2, &["enhancement"])),
+            Some(pr(3, &["semver:major"])),
+        ]);
+        let label_bump = infer_bump(&prs, &labels);
+        assert_eq!(label_bump.inferred.unwrap().bump, CoreBump::Minor);
+        // The default names no longer count
+        let unlabeled: Vec<_> = label_bump.unlabeled.iter().map(|pr| pr.number).collect();
+        assert_eq!(unlabeled, [3]);
+    }
+
+    #[test]
+    fn unlabeled_prs() {
+        let prs = commit_prs(vec![
+            Some(pr(1, &["documentation"])),
+            Some(pr(2, &[])),
+            Some(pr(1, &["documentation"])),
+            None,
+        ]);
+        let label_bump = infer_bump(&prs, &BumpLabels::default());
+        assert!(label_bump.inferred.is_none());
+        let unlabeled: Vec<_> = label_bump.unlabeled.iter().map(|pr| pr.number).collect();
+        assert_eq!(unlabeled, [1, 2]);
+    }
+
+    #[test]
+    fn no_prs() {
+        for prs in [Vec::new(), commit_prs(vec![None, None])] {
+            let label_bump = infer_bump(&prs, &BumpLabels::default());
+            assert!(label_bump.inferred.is_none());
+            assert!(label_bump.unlabeled.is_empty());
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use crate::conventional::ConventionalCommit;
+use crate::forge::ForgeRepo;
+use crate::notes::LabelGroup;
+use std::fmt::Write as FmtWrite;
+
+const HEADER: &str = "# Changelog
+
+All notable changes to this project will be documented in this file.
+
+The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/),
+and this project adheres to [Semantic Versioning](https://semver.org/spec/v2.0.0.html).
+";
+
+const UNRELEASED: &str = "## [Unreleased]";
+
+/// Keep a Changelog sections, in the order they are written.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Section {
+    Added,
+    Changed,
+    Deprecated,
+    Removed,
+    Fixed,
+    Security,
+}
+
+impl Section {
+    const ALL: [Self; 6] = [
+        Self::Added,
+        Self::Changed,
+        Self::Deprecated,
+        Self::Removed,
+        Self::Fixed,
+        Self::Security,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Added => "Added",
+            Self::Changed => "Changed",
+            Self::Deprecated => "Deprecated",
+            Self::Removed => "Removed",
+            Self::Fixed => "Fixed",
+            Self::Security => "Security",
+        }
+    }
+
+    /// The section of a `### <title>` heading, titles are compared case-insensitively.
+    fn from_title(title: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|section| section.title().eq_ignore_ascii_case(title.trim()))
+    }
+}
+
+/// Pick the section for a commit message or PR title and strip the Conventional Commit prefix.
+/// PR labels win over the section guessed from the message, commits that are not
+/// Conventional Commits are classified by their first word.
+///
+/// Breaking changes, from the commit (`feat!:`, `BREAKING CHANGE:`) or a PR label,
+/// always go to "Changed" with a **BREAKING** marker.
+pub fn classify(message: &str, labels: LabelGroup) -> (Section, String) {
+    let summary = message.lines().next().unwrap_or_default().trim();
+    let breaking_label = labels == LabelGroup::Breaking;
+
+    if let Some(cc) = ConventionalCommit::parse(message) {
+        let text = match &cc.scope {
+            Some(scope) => format!("{scope}: {}", cc.description),
+            None => cc.description,
+        };
+        if cc.breaking || breaking_label {
+            return breaking(&text);
+        }
+        let section = labels.section().unwrap_or(match cc.kind.as_str() {
+            "feat" => Section::Added,
+            "fix" => Section::Fixed,
+            "remove" => Section::Removed,
+            _ => Section::Changed,
+        });
+        return (section, text);
+    }
+    if breaking_label {
+        return breaking(summary);
+    }
+
+    let first_word = summary
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let section = labels.section().unwrap_or(match first_word.as_str() {
+        "add" | "adds" | "added" => Section::Added,
+        "fix" | "fixes" | "fixed" => Section::Fixed,
+        "remove" | "removes" | "removed" | "delete" | "deleted" | "drop" => Section::Removed,
+        _ => Section::Changed,
+    });
+    (section, summary.to_string())
+}
+
+fn breaking(text: &str) -> (Section, String) {
+    (Section::Changed, format!("**BREAKING** {text}"))
+}
+
+/// Render a `## [x.y.z] - YYYY-MM-DD` section with the entries grouped by section.
+fn render_release(version: &str, date: &str, entries: &[(Section, String)]) -> String {
+    let mut release = String::new();
+    writeln!(release, "## [{version}] - {date}").expect("Should never fail");
+
+    let mut sections: Vec<Section> = entries.iter().map(|(s, _)| *s).collect();
+    sections.sort();
+    sections.dedup();
+
+    for section in sections {
+        writeln!(release, "\n### {}\n", section.title()).expect("Should never fail");
+        for (_, text) in entries.iter().filter(|(s, _)| *s == section) {
+            writeln!(release, "- {text}").expect("Should never fail");
+        }
+    }
+    release
+}
+
+/// Prepend the release below `## [Unreleased]` and update the compare links,
+/// keeping the line endings of the existing file.
+///
+/// Entries written by hand under `## [Unreleased]` belong to this release, they are
+/// merged into the matching sections before the generated entries.
+pub fn update(
+    existing: Option<&str>,
+    version: &str,
+    date: &str,
+    entries: &[(Section, String)],
+    previous_tag: Option<&str>,
+    new_tag: &str,
+    forge: Option<&ForgeRepo>,
+) -> String {
+    // Line endings are normalized and restored at the end
+    let existing = existing.unwrap_or(HEADER);
+    let crlf = existing.contains("\r\n");
+    let existing = existing.replace("\r\n", "\n");
+    let (body, mut links) = split_links(&existing);
+
+    // Split the body into the preamble, the unreleased notes and the older releases
+    let (preamble, rest) = match body.find(UNRELEASED) {
+        Some(idx) => (&body[..idx], &body[idx + UNRELEASED.len()..]),
+        None => match body.find("\n## ") {
+            Some(idx) => (&body[..idx + 1], &body[idx + 1..]),
+            None => (body, ""),
+        },
+    };
+    let (unreleased, older) = if body.contains(UNRELEASED) {
+        match rest.find("\n## ") {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => (rest, ""),
+        }
+    } else {
+        ("", rest)
+    };
+
+    let mut merged = parse_entries(unreleased);
+    merged.extend_from_slice(entries);
+    let release = render_release(version, date, &merged);
+
+    let mut changelog = String::new();
+    write!(
+        changelog,
+        "{}\n\n{UNRELEASED}\n\n{}",
+        preamble.trim_end(),
+        release.trim_end()
+    )
+    .expect("Should never fail");
+    if !older.trim().is_empty() {
+        write!(changelog, "\n\n{}", older.trim_end()).expect("Should never fail");
+    }
+    changelog.push('\n');
+
+    if let Some(forge) = forge {
+        links.retain(|l| {
+            !l.starts_with("[Unreleased]:") && !l.starts_with(&format!("[{version}]:"))
+        });
+        let release_link = match previous_tag {
+            Some(previous_tag) => forge.compare_url(previous_tag, new_tag),
+            None => forge.release_url(new_tag),
+        };
+        links.insert(0, format!("[{version}]: {release_link}"));
+        links.insert(
+            0,
+            format!("[Unreleased]: {}", forge.compare_url(new_tag, "HEAD")),
+        );
+    }
+    if !links.is_empty() {
+        write!(changelog, "\n{}\n", links.join("\n")).expect("Should never fail");
+    }
+    if crlf {
+        changelog = changelog.replace('\n', "\r\n");
+    }
+    changelog
+}
+
+/// The list items of hand-written notes with their section. Items outside a known
+/// `### <section>` heading go to "Changed", continuation lines stay with their item.
+fn parse_entries(notes: &str) -> Vec<(Section, String)> {
+    let mut entries: Vec<(Section, String)> = Vec::new();
+    let mut section = Section::Changed;
+    // Whether the last line belonged to an item, so continuation lines can follow it
+    let mut in_item = false;
+
+    for line in notes.lines() {
+        let line = line.trim_end();
+        if let Some(title) = line.strip_prefix("### ") {
+            section = Section::from_title(title).unwrap_or(Section::Changed);
+            in_item = false;
+        } else if let Some(text) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            entries.push((section, text.trim().to_string()));
+            in_item = true;
+        } else if line.trim().is_empty() {
+            in_item = false;
+        } else if in_item && let Some((_, text)) = entries.last_mut() {
+            write!(text, "\n{line}").expect("Should never fail");
+        } else {
+            // Free text is kept as an entry of its own
+            entries.push((section, line.trim().to_string()));
+            in_item = true;
+        }
+    }
+    entries
+}
+
+/// Split the trailing link reference definitions (`[1.0.0]: https://...`) from the body.
+fn split_links(contents: &str) -> (&str, Vec<String>) {
+    let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+    let body_len = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty() && !is_link(line))
+        .map_or(0, |idx| idx + 1);
+
+    let links = lines[body_len..]
+        .iter()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    let body_end = lines[..body_len].iter().map(|line| line.len()).sum();
+    (&contents[..body_end], links)
+}
+
+fn is_link(line: &str) -> bool {
+    line.starts_with('[') && line.contains("]: ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::ForgeKind;
+
+    fn forge() -> ForgeRepo {
+        ForgeRepo {
+            kind: ForgeKind::GitHub,
+            authority: "github.com".to_string(),
+            owner: "owner".to_string(),
+            name: "repo".to_string(),
+            api_url: "https://api.github.com".to_string(),
+        }
+    }
+
+    fn entries(entries: &[(Section, &str)]) -> Vec<(Section, String)> {
+        entries
+            .iter()
+            .map(|(section, text)| (*section, text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn classify_messages() {
+        let cases = [
+            ("feat: Add a flag", Section::Added, "Add a flag"),
+            ("fix(cli): Exit code", Section::Fixed, "cli: Exit code"),
+            ("remove: Old flag", Section::Removed, "Old flag"),
+            ("revert: New flag", Section::Changed, "New flag"),
+            ("docs: Typos", Section::Changed, "Typos"),
+            ("Fixed a crash\n\nBody", Section::Fixed, "Fixed a crash"),
+            ("Drop Node 16", Section::Removed, "Drop Node 16"),
+            ("Update deps", Section::Changed, "Update deps"),
+        ];
+        for (message, section, text) in cases {
+            assert_eq!(
+                classify(message, LabelGroup::Other),
+                (section, text.to_string()),
+                "{message}"
+            );
+        }
+
+        // Labels win over the message
+        let cases = [
+            ("fix: Typo", LabelGroup::Features, Section::Added, "Typo"),
+            (
+                "Update deps",
+                LabelGroup::BugFixes,
+                Section::Fixed,
+                "Update deps",
+            ),
+        ];
+        for (message, labels, section, text) in cases {
+            assert_eq!(
+                classify(message, labels),
+                (section, text.to_string()),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn classify_breaking() {
+        let cases = [
+            ("feat!: Drop the flag", LabelGroup::Other, "Drop the flag"),
+            (
+                "fix(api): Rename\n\nBREAKING CHANGE: Renamed",
+                LabelGroup::Other,
+                "api: Rename",
+            ),
+            ("feat: New API", LabelGroup::Breaking, "New API"),
+            (
+                "Rework the config",
+                LabelGroup::Breaking,
+                "Rework the config",
+            ),
+        ];
+        for (message, labels, text) in cases {
+            assert_eq!(
+                classify(message, labels),
+                (Section::Changed, format!("**BREAKING** {text}")),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn render_sections_in_order() {
+        let release = render_release(
+            "1.1.0",
+            "2026-10-16",
+            &entries(&[
+                (Section::Fixed, "Crash"),
+                (Section::Added, "Flag"),
+                (Section::Fixed, "Typo"),
+                (Section::Removed, "Old flag"),
+            ]),
+        );
+        assert_eq!(
+            release,
+            "## [1.1.0] - 2026-10-16\n\n### Added\n\n- Flag\n\n### Removed\n\n- Old flag\n\n### Fixed\n\n- Crash\n- Typo\n"
+        );
+    }
+
+    #[test]
+    fn new_file() {
+        let changelog = update(
+            None,
+            "0.1.0",
+            "2026-10-16",
+            &entries(&[(Section::Added, "First")]),
+            None,
+            "v0.1.0",
+            Some(&forge()),
+        );
+        assert_eq!(
+            changelog,
+            format!(
+                "{}\n{UNRELEASED}\n\n## [0.1.0] - 2026-10-16\n\n### Added\n\n- First\n\n\
+                 [Unreleased]: https://github.com/owner/repo/compare/v0.1.0...HEAD\n\
+                 [0.1.0]: https://github.com/owner/repo/releases/tag/v0.1.0\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn merge_unreleased_notes() {
+        let existing = "# Changelog\n\n## [Unreleased]\n\n### Fixed\n\n- Manual fix\n  spanning two lines\n\n### Security\n\n- Patched CVE\n\n## [1.0.0] - 2026-01-01\n\n### Added\n\n- Old\n";
+        let changelog = update(
+            Some(existing),
+            "1.1.0",
+            "2026-10-16",
+            &entries(&[(Section::Added, "Flag"), (Section::Fixed, "Crash")]),
+            Some("v1.0.0"),
+            "v1.1.0",
+            None,
+        );
+        assert_eq!(
+            changelog,
+            "# Changelog\n\n## [Unreleased]\n\n## [1.1.0] - 2026-10-16\n\n\
+             ### Added\n\n- Flag\n\n\
+             ### Fixed\n\n- Manual fix\n  spanning two lines\n- Crash\n\n\
+             ### Security\n\n- Patched CVE\n\n\
+             ## [1.0.0] - 2026-01-01\n\n### Added\n\n- Old\n"
+        );
+        assert_eq!(changelog.matches("### Fixed").count(), 1);
+    }
+
+    #[test]
+    fn rewrite_compare_links() {
+        let existing = "# Changelog\n\n## [Unreleased]\n\n## [1.0.0] - 2026-01-01\n\n- Old\n\n\
+                        [Unreleased]: https://github.com/owner/repo/compare/v1.0.0...HEAD\n\
+                        [1.0.0]: https://github.com/owner/repo/releases/tag/v1.0.0\n";
+        let changelog = update(
+            Some(existing),
+            "1.1.0",
+            "2026-10-16",
+            &entries(&[(Section::Changed, "**BREAKING** New API")]),
+            Some("v1.0.0"),
+            "v1.1.0",
+            Some(&forge()),
+        );
+        assert_eq!(
+            changelog,
+            "# Changelog\n\n## [Unreleased]\n\n## [1.1.0] - 2026-10-16\n\n\
+             ### Changed\n\n- **BREAKING** New API\n\n\
+             ## [1.0.0] - 2026-01-01\n\n- Old\n\n\
+             [Unreleased]: https://github.com/owner/repo/compare/v1.1.0...HEAD\n\
+             [1.1.0]: https://github.com/owner/repo/compare/v1.0.0...v1.1.0\n\
+             [1.0.0]: https://github.com/owner/repo/releases/tag/v1.0.0\n"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let existing = "# Changelog\r\n\r\n## [Unreleased]\r\n\r\n- Manual\r\n\r\n\
+                        ## [1.0.0] - 2026-01-01\r\n\r\n- Old\r\n\r\n\
+                        [1.0.0]: https://example.com/v1.0.0\r\n";
+        let (body, links) = split_links(existing);
+        assert!(body.ends_with("- Old\r\n"), "{body:?}");
+        assert_eq!(links, ["[1.0.0]: https://example.com/v1.0.0"]);
+
+        let changelog = update(
+            Some(existing),
+            "1.1.0",
+            "2026-10-16",
+            &entries(&[(Section::Added, "Flag")]),
+            Some("v1.0.0"),
+            "v1.1.0",
+            None,
+        );
+        assert_eq!(
+            changelog,
+            "# Changelog\r\n\r\n## [Unreleased]\r\n\r\n## [1.1.0] - 2026-10-16\r\n\r\n\
+             ### Added\r\n\r\n- Flag\r\n\r\n### Changed\r\n\r\n- Manual\r\n\r\n\
+             ## [1.0.0] - 2026-01-01\r\n\r\n- Old\r\n\r\n\
+             [1.0.0]: https://example.com/v1.0.0\r\n"
+        );
+    }
+}
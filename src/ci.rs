@@ -0,0 +1,115 @@
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Results of a run, for the steps after it in a CI pipeline.
+pub struct Outputs<'a> {
+    /// Version without the `v`, empty when nothing is tagged
+    pub new_version: Option<String>,
+    pub previous_version: Option<String>,
+    /// Name of the new tag
+    pub tag: Option<&'a str>,
+    /// Release notes as Markdown
+    pub changelog: &'a str,
+    /// Whether the tag was created and published, false for dry runs, `show` and failures
+    pub released: bool,
+}
+
+impl Outputs<'_> {
+    fn values(&self) -> [(&'static str, String); 5] {
+        [
+            ("new_version", self.new_version.clone().unwrap_or_default()),
+            (
+                "previous_version",
+                self.previous_version.clone().unwrap_or_default(),
+            ),
+            ("tag", self.tag.unwrap_or_default().to_string()),
+            ("changelog", self.changelog.trim_end().to_string()),
+            ("released", self.released.to_string()),
+        ]
+    }
+
+    /// Markdown for the job summary page.
+    fn summary(&self) -> String {
+        let title = match (self.tag, self.released) {
+            (Some(tag), true) => format!("Released {tag}"),
+            (Some(tag), false) => format!("Would release {tag}"),
+            (None, _) => "Unreleased changes".to_string(),
+        };
+        let previous = match &self.previous_version {
+            Some(previous) => format!("Previous version: {previous}\n\n"),
+            None => String::new(),
+        };
+        let changelog = if self.changelog.is_empty() {
+            "No new commits since the latest tag.\n"
+        } else {
+            self.changelog
+        };
+        format!("## {title}\n\n{previous}{changelog}\n")
+    }
+}
+
+/// `CI=true` is set by GitHub Actions, GitLab CI and most other CI services.
+pub fn is_ci() -> bool {
+    std::env::var("CI").is_ok_and(|ci| ci == "true")
+}
+
+/// Write the outputs to `$GITHUB_OUTPUT` and `$GITHUB_STEP_SUMMARY` if set,
+/// and to the dotenv file for GitLab CI if requested.
+pub fn write_outputs(outputs: &Outputs, dotenv: Option<&Path>) -> MietteResult<()> {
+    if let Some(path) = std::env::var_os("GITHUB_OUTPUT") {
+        let mut contents = String::new();
+        for (key, value) in outputs.values() {
+            if value.contains('\n') {
+                // Multiline values need a delimiter that does not occur in them
+                let delimiter = delimiter(&value);
+                contents.push_str(&format!("{key}<<{delimiter}\n{value}\n{delimiter}\n"));
+            } else {
+                contents.push_str(&format!("{key}={value}\n"));
+            }
+        }
+        append(Path::new(&path), &contents)?;
+    }
+    if let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") {
+        append(Path::new(&path), &outputs.summary())?;
+    }
+    if let Some(path) = dotenv {
+        // GitLab dotenv reports are single-line, newlines are escaped
+        let contents: String = outputs
+            .values()
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key.to_uppercase(), value.replace('\n', "\\n")))
+            .collect();
+        std::fs::write(path, contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn append(path: &Path, contents: &str) -> MietteResult<()> {
+    tracing::info!("Writing CI outputs to {}", path.display());
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+fn delimiter(value: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let mut delimiter = format!("TAGGE_EOF_{nanos:x}");
+    while value.contains(&delimiter) {
+        delimiter.push('_');
+    }
+    delimiter
+}
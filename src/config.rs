@@ -0,0 +1,742 @@
+use crate::args::CliArgs;
+use crate::bump_labels::BumpLabels;
+use crate::forge::ForgeHosts;
+use crate::output;
+use crate::output::say;
+use crate::signing::SignMode;
+use crate::version_files::VersionFile;
+use clap::ArgMatches;
+use clap::ValueEnum;
+use clap::parser::ValueSource;
+use colored::Colorize;
+use git2::Repository;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const REPO_CONFIG_FILE: &str = ".tagge.toml";
+
+/// Persistent defaults from `.tagge.toml`, `[package.metadata.tagge]` in Cargo.toml
+/// or the user config (`~/.config/tagge/config.toml` on Linux).
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FileConfig {
+    pub use_sha: Option<bool>,
+    pub use_pr: Option<bool>,
+    pub pr_titles: Option<bool>,
+    pub bump_labels: Option<BumpLabels>,
+    pub suffix: Option<String>,
+    pub metadata: Option<String>,
+    pub changelog: Option<String>,
+    pub version_files: Option<Vec<VersionFile>>,
+    pub assets: Option<Vec<String>>,
+    pub sign: Option<SignMode>,
+    pub push: Option<bool>,
+    pub no_fetch: Option<bool>,
+    pub branches: Option<Vec<String>>,
+    pub remote: Option<String>,
+    pub ssh_key: Option<PathBuf>,
+    pub forge_hosts: Option<ForgeHosts>,
+    pub debug: Option<bool>,
+}
+
+/// Where an effective setting came from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Cli,
+    Env,
+    RepoConfig(PathBuf),
+    UserConfig(PathBuf),
+    /// The remote the current branch tracks
+    Upstream(String),
+    Default,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cli => write!(f, "command line"),
+            Self::Env => write!(f, "environment"),
+            Self::RepoConfig(path) => write!(f, "repo config {}", path.display()),
+            Self::UserConfig(path) => write!(f, "user config {}", path.display()),
+            Self::Upstream(branch) => write!(f, "upstream of branch {branch}"),
+            Self::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// The `TAGGE_*` environment variables, the layer between the command line and the config files.
+#[derive(Default)]
+pub struct Env(HashMap<String, String>);
+
+impl Env {
+    pub fn from_process() -> Self {
+        Self(
+            std::env::vars_os()
+                .filter_map(|(name, value)| {
+                    Some((name.into_string().ok()?, value.into_string().ok()?))
+                })
+                .filter(|(name, _)| name.starts_with("TAGGE_"))
+                .collect(),
+        )
+    }
+
+    /// Empty variables count as unset.
+    fn string(&self, name: &str) -> Option<String> {
+        self.0.get(name).filter(|value| !value.is_empty()).cloned()
+    }
+
+    /// Anything but a false-like value (0, false, no, off, ...) is true.
+    fn flag(&self, name: &str) -> Option<bool> {
+        self.string(name).map(|value| {
+            !matches!(
+                value.to_ascii_lowercase().as_str(),
+                "0" | "false" | "f" | "no" | "n" | "off"
+            )
+        })
+    }
+
+    fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.string(name).map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .collect()
+        })
+    }
+
+    fn value_enum<T: ValueEnum>(&self, name: &str) -> MietteResult<Option<T>> {
+        self.string(name)
+            .map(|value| {
+                T::from_str(&value, true).map_err(|e| miette!("Invalid {name} '{value}': {e}"))
+            })
+            .transpose()
+    }
+}
+
+/// Loaded configuration layers, lowest precedence last.
+#[derive(Default)]
+pub struct Config {
+    repo: Option<(PathBuf, FileConfig)>,
+    /// `[package.metadata.tagge]` in Cargo.toml
+    cargo: Option<(PathBuf, FileConfig)>,
+    user: Option<(PathBuf, FileConfig)>,
+    /// Current branch and the remote it tracks, the default remote
+    upstream: Option<(String, String)>,
+}
+
+impl Config {
+    pub fn load(repo: &Repository) -> MietteResult<Self> {
+        let (repo_config, cargo_config) = match repo.workdir() {
+            Some(workdir) => (load_repo_config(workdir)?, load_cargo_config(workdir)?),
+            None => (None, None),
+        };
+        let user_config = match dirs::config_dir() {
+            Some(dir) => {
+                let path = dir.join("tagge").join("config.toml");
+                if path.is_file() {
+                    Some((path.clone(), read_file_config(&path)?))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        if let Some((path, _)) = &repo_config {
+            tracing::info!("Loaded repo config from {}", path.display());
+        }
+        if let Some((path, _)) = &cargo_config {
+            tracing::info!("Loaded [package.metadata.tagge] from {}", path.display());
+        }
+        if let Some((path, _)) = &user_config {
+            tracing::info!("Loaded user config from {}", path.display());
+        }
+
+        Ok(Self {
+            repo: repo_config,
+            cargo: cargo_config,
+            user: user_config,
+            upstream: upstream_remote(repo),
+        })
+    }
+
+    /// Fill every setting not given on the command line from `env`, else from the config files.
+    /// Precedence: CLI > env > .tagge.toml > [package.metadata.tagge] > user config.
+    pub fn apply(
+        &self,
+        cli_args: &mut CliArgs,
+        matches: &ArgMatches,
+        env: &Env,
+    ) -> MietteResult<Vec<Setting>> {
+        let mut settings = Vec::new();
+        // The tagging options are given after `bump` or `init`, or without a subcommand
+        let tag_matches = match matches.subcommand() {
+            Some(("bump" | "init", sub_matches)) => sub_matches,
+            _ => matches,
+        };
+
+        let source = self.layer(
+            matches,
+            "use_sha",
+            env.flag("TAGGE_USE_SHA"),
+            &mut cli_args.use_sha,
+            |c| c.use_sha,
+        );
+        settings.push(Setting::new("use-sha", cli_args.use_sha, source));
+
+        let source = self.layer(
+            matches,
+            "use_pr",
+            env.flag("TAGGE_USE_PR"),
+            &mut cli_args.use_pr,
+            |c| c.use_pr,
+        );
+        settings.push(Setting::new("use-pr", cli_args.use_pr, source));
+
+        let source = self.layer(
+            matches,
+            "pr_titles",
+            env.flag("TAGGE_PR_TITLES"),
+            &mut cli_args.pr_titles,
+            |c| c.pr_titles,
+        );
+        settings.push(Setting::new("pr-titles", cli_args.pr_titles, source));
+
+        // Only in the config files, there is no command line option
+        let source = match self.file_value(|c| c.bump_labels.clone()) {
+            Some((bump_labels, source)) => {
+                cli_args.bump_labels = bump_labels;
+                source
+            }
+            None => Source::Default,
+        };
+        settings.push(Setting::new("bump-labels", &cli_args.bump_labels, source));
+
+        let source = self.layer(
+            tag_matches,
+            "suffix",
+            env.string("TAGGE_SUFFIX").map(Some),
+            &mut cli_args.tag_args.suffix,
+            |c| c.suffix.clone().map(Some),
+        );
+        settings.push(Setting::new(
+            "suffix",
+            display_opt(&cli_args.tag_args.suffix),
+            source,
+        ));
+
+        let source = self.layer(
+            tag_matches,
+            "metadata",
+            env.string("TAGGE_METADATA").map(Some),
+            &mut cli_args.tag_args.metadata,
+            |c| c.metadata.clone().map(Some),
+        );
+        settings.push(Setting::new(
+            "metadata",
+            display_opt(&cli_args.tag_args.metadata),
+            source,
+        ));
+
+        let source = self.layer(
+            tag_matches,
+            "changelog",
+            env.string("TAGGE_CHANGELOG").map(Some),
+            &mut cli_args.tag_args.changelog,
+            |c| c.changelog.clone().map(Some),
+        );
+        settings.push(Setting::new(
+            "changelog",
+            display_opt(&cli_args.tag_args.changelog),
+            source,
+        ));
+
+        let source = self.layer(
+            tag_matches,
+            "version_files",
+            None,
+            &mut cli_args.tag_args.version_files,
+            |c| c.version_files.clone(),
+        );
+        settings.push(Setting::new(
+            "version-files",
+            cli_args
+                .tag_args
+                .version_files
+                .iter()
+                .map(|f| f.path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            source,
+        ));
+
+        let source = self.layer(
+            tag_matches,
+            "assets",
+            None,
+            &mut cli_args.tag_args.assets,
+            |c| c.assets.clone(),
+        );
+        settings.push(Setting::new(
+            "assets",
+            cli_args.tag_args.assets.join(", "),
+            source,
+        ));
+
+        let source = self.layer(
+            tag_matches,
+            "sign",
+            env.value_enum("TAGGE_SIGN")?.map(Some),
+            &mut cli_args.tag_args.sign,
+            |c| c.sign.map(Some),
+        );
+        settings.push(Setting::new(
+            "sign",
+            cli_args
+                .tag_args
+                .sign
+                .map_or("<git config>".to_string(), |mode| mode.to_string()),
+            source,
+        ));
+
+        let source = self.layer(
+            tag_matches,
+            "push",
+            env.flag("TAGGE_PUSH"),
+            &mut cli_args.tag_args.push,
+            |c| c.push,
+        );
+        settings.push(Setting::new("push", cli_args.tag_args.push, source));
+
+        let source = self.layer(
+            matches,
+            "no_fetch",
+            env.flag("TAGGE_NO_FETCH"),
+            &mut cli_args.no_fetch,
+            |c| c.no_fetch,
+        );
+        settings.push(Setting::new("no-fetch", cli_args.no_fetch, source));
+
+        let source = self.layer(
+            matches,
+            "branches",
+            env.list("TAGGE_BRANCHES"),
+            &mut cli_args.branches,
+            |c| c.branches.clone(),
+        );
+        settings.push(Setting::new(
+            "branches",
+            cli_args.branches.join(", "),
+            source,
+        ));
+
+        let mut source = self.layer(
+            matches,
+            "remote",
+            env.string("TAGGE_REMOTE").map(Some),
+            &mut cli_args.remote,
+            |c| c.remote.clone().map(Some),
+        );
+        if cli_args.remote.is_none()
+            && let Some((branch, remote)) = &self.upstream
+        {
+            cli_args.remote = Some(remote.clone());
+            source = Source::Upstream(branch.clone());
+        }
+        settings.push(Setting::new("remote", cli_args.remote(), source));
+
+        let source = self.layer(
+            matches,
+            "ssh_key",
+            env.string("TAGGE_SSH_KEY")
+                .map(|path| Some(PathBuf::from(path))),
+            &mut cli_args.ssh_key,
+            |c| c.ssh_key.as_deref().map(expand_home).map(Some),
+        );
+        settings.push(Setting::new(
+            "ssh-key",
+            cli_args
+                .ssh_key
+                .as_ref()
+                .map_or("<unset>".to_string(), |path| path.display().to_string()),
+            source,
+        ));
+
+        let source = match self.file_value(|c| c.forge_hosts.clone()) {
+            Some((forge_hosts, source)) => {
+                cli_args.forge_hosts = forge_hosts;
+                source
+            }
+            None => Source::Default,
+        };
+        settings.push(Setting::new(
+            "forge-hosts",
+            cli_args
+                .forge_hosts
+                .iter()
+                .map(|(host, forge)| format!("{host}: {forge}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            source,
+        ));
+
+        let source = self.layer(
+            matches,
+            "debug",
+            env.flag("TAGGE_DEBUG"),
+            &mut cli_args.debug,
+            |c| c.debug,
+        );
+        settings.push(Setting::new("debug", cli_args.debug, source));
+
+        Ok(settings)
+    }
+
+    fn layer<T>(
+        &self,
+        matches: &ArgMatches,
+        id: &str,
+        env: Option<T>,
+        value: &mut T,
+        pick: impl Fn(&FileConfig) -> Option<T>,
+    ) -> Source {
+        if matches.value_source(id) == Some(ValueSource::CommandLine) {
+            return Source::Cli;
+        }
+        if let Some(v) = env {
+            *value = v;
+            return Source::Env;
+        }
+
+        match self.file_value(pick) {
+            Some((v, source)) => {
+                *value = v;
+                source
+            }
+            None => Source::Default,
+        }
+    }
+
+    /// The value from the repo config, else from Cargo.toml, else from the user config.
+    fn file_value<T>(&self, pick: impl Fn(&FileConfig) -> Option<T>) -> Option<(T, Source)> {
+        for (path, config) in [&self.repo, &self.cargo].into_iter().flatten() {
+            if let Some(v) = pick(config) {
+                return Some((v, Source::RepoConfig(path.clone())));
+            }
+        }
+        if let Some((path, config)) = &self.user
+            && let Some(v) = pick(config)
+        {
+            return Some((v, Source::UserConfig(path.clone())));
+        }
+        None
+    }
+}
+
+/// Effective value of a single setting, for `config show`.
+pub struct Setting {
+    key: &'static str,
+    value: String,
+    source: Source,
+}
+
+impl Setting {
+    fn new(key: &'static str, value: impl ToString, source: Source) -> Self {
+        Self {
+            key,
+            value: value.to_string(),
+            source,
+        }
+    }
+}
+
+pub fn print_settings(settings: &[Setting]) {
+    if output::is_json() {
+        let settings: Vec<_> = settings
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "key": s.key,
+                    "value": s.value,
+                    "source": s.source.to_string(),
+                })
+            })
+            .collect();
+        output::print_json(&settings);
+        return;
+    }
+    let width = settings.iter().map(|s| s.key.len()).max().unwrap_or(0);
+    for setting in settings {
+        say!(
+            "{:width$} = {} {}",
+            setting.key,
+            setting.value,
+            format!("({})", setting.source).dimmed()
+        );
+    }
+}
+
+/// The remote configured with `branch.<name>.remote` for the checked out branch.
+fn upstream_remote(repo: &Repository) -> Option<(String, String)> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    let remote = repo.branch_upstream_remote(head.name()?).ok()?;
+    let remote = remote.as_str()?;
+    // "." tracks a local branch
+    if remote == "." {
+        return None;
+    }
+    let branch = head.shorthand()?.to_string();
+    tracing::info!("Branch {branch} tracks remote {remote}");
+    Some((branch, remote.to_string()))
+}
+
+/// Resolve a leading `~` to the home directory, the shell does not expand paths in config files.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn display_opt(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "<unset>".to_string())
+}
+
+fn load_repo_config(workdir: &Path) -> MietteResult<Option<(PathBuf, FileConfig)>> {
+    let path = workdir.join(REPO_CONFIG_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some((path.clone(), read_file_config(&path)?)))
+}
+
+/// `[package.metadata.tagge]` of a Rust project, below `.tagge.toml`.
+fn load_cargo_config(workdir: &Path) -> MietteResult<Option<(PathBuf, FileConfig)>> {
+    let cargo_toml = workdir.join("Cargo.toml");
+    if !cargo_toml.is_file() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(&cargo_toml).into_diagnostic()?;
+    let manifest: toml::Table = toml::from_str(&contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse {}", cargo_toml.display()))?;
+
+    let Some(table) = manifest
+        .get("package")
+        .and_then(|p| p.get("metadata"))
+        .and_then(|m| m.get("tagge"))
+    else {
+        return Ok(None);
+    };
+    let config = table
+        .clone()
+        .try_into()
+        .into_diagnostic()
+        .wrap_err_with(|| {
+            format!(
+                "Invalid [package.metadata.tagge] in {}",
+                cargo_toml.display()
+            )
+        })?;
+    Ok(Some((cargo_toml, config)))
+}
+
+fn read_file_config(path: &Path) -> MietteResult<FileConfig> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid config file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::VersionBump;
+    use clap::CommandFactory;
+    use clap::FromArgMatches;
+
+    fn layer(path: &str, contents: &str) -> Option<(PathBuf, FileConfig)> {
+        Some((PathBuf::from(path), toml::from_str(contents).unwrap()))
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Env {
+        Env(vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect())
+    }
+
+    fn apply(config: &Config, env: &Env, args: &[&str]) -> (CliArgs, Vec<Setting>) {
+        let matches = CliArgs::command().try_get_matches_from(args).unwrap();
+        let mut cli_args = CliArgs::from_arg_matches(&matches).unwrap();
+        let settings = config.apply(&mut cli_args, &matches, env).unwrap();
+        (cli_args, settings)
+    }
+
+    fn source_of(settings: &[Setting], key: &str) -> String {
+        settings
+            .iter()
+            .find(|s| s.key == key)
+            .map(|s| s.source.to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn layering_precedence() {
+        let config = Config {
+            repo: layer(
+                ".tagge.toml",
+                "suffix = \"repo\"\nremote = \"repo\"\nuse-pr = true\n",
+            ),
+            cargo: layer(
+                "Cargo.toml",
+                "suffix = \"cargo\"\nremote = \"cargo\"\nmetadata = \"cargo\"\n",
+            ),
+            user: layer(
+                "config.toml",
+                "suffix = \"user\"\nremote = \"user\"\nmetadata = \"user\"\nbranches = [\"user\"]\n",
+            ),
+            upstream: None,
+        };
+        let vars = env(&[
+            ("TAGGE_SUFFIX", "env"),
+            ("TAGGE_REMOTE", "env"),
+            ("TAGGE_USE_PR", "0"),
+            ("TAGGE_METADATA", ""),
+        ]);
+        let (cli_args, settings) = apply(&config, &vars, &["tagge_rs", "--suffix", "cli"]);
+
+        // CLI > env
+        assert_eq!(cli_args.tag_args.suffix.as_deref(), Some("cli"));
+        assert_eq!(source_of(&settings, "suffix"), "command line");
+        // env > .tagge.toml
+        assert_eq!(cli_args.remote.as_deref(), Some("env"));
+        assert_eq!(source_of(&settings, "remote"), "environment");
+        assert!(!cli_args.use_pr);
+        assert_eq!(source_of(&settings, "use-pr"), "environment");
+        // [package.metadata.tagge] > user config, empty variables are unset
+        assert_eq!(cli_args.tag_args.metadata.as_deref(), Some("cargo"));
+        assert_eq!(source_of(&settings, "metadata"), "repo config Cargo.toml");
+        // Only in the user config
+        assert_eq!(cli_args.branches, ["user"]);
+        assert_eq!(source_of(&settings, "branches"), "user config config.toml");
+        // Nowhere
+        assert_eq!(cli_args.tag_args.changelog, None);
+        assert_eq!(source_of(&settings, "changelog"), "default");
+
+        // .tagge.toml > [package.metadata.tagge]
+        let (cli_args, settings) = apply(&config, &Env::default(), &["tagge_rs"]);
+        assert_eq!(cli_args.tag_args.suffix.as_deref(), Some("repo"));
+        assert_eq!(source_of(&settings, "suffix"), "repo config .tagge.toml");
+        assert_eq!(cli_args.remote.as_deref(), Some("repo"));
+    }
+
+    #[test]
+    fn tagging_options_after_subcommand() {
+        let config = Config {
+            repo: layer(".tagge.toml", "changelog = \"NEWS.md\"\npush = true\n"),
+            ..Default::default()
+        };
+        let (cli_args, settings) = apply(
+            &config,
+            &Env::default(),
+            &["tagge_rs", "bump", "patch", "--push"],
+        );
+        assert_eq!(cli_args.tag_args.changelog.as_deref(), Some("NEWS.md"));
+        assert_eq!(source_of(&settings, "push"), "command line");
+    }
+
+    #[test]
+    fn switches_turn_off_config() {
+        let config = Config {
+            repo: layer(
+                ".tagge.toml",
+                "push = true\nuse-pr = true\nno-fetch = true\n",
+            ),
+            ..Default::default()
+        };
+        let vars = env(&[("TAGGE_USE_SHA", "true")]);
+        let (cli_args, settings) = apply(
+            &config,
+            &vars,
+            &[
+                "tagge_rs",
+                "bump",
+                "patch",
+                "--push=false",
+                "--use-pr=false",
+                "-s=false",
+            ],
+        );
+        assert!(!cli_args.tag_args.push);
+        assert_eq!(source_of(&settings, "push"), "command line");
+        assert!(!cli_args.use_pr);
+        assert_eq!(source_of(&settings, "use-pr"), "command line");
+        assert!(!cli_args.use_sha);
+        assert_eq!(source_of(&settings, "use-sha"), "command line");
+        assert!(cli_args.no_fetch);
+
+        // Without a value a switch is on, and does not take the next argument
+        let (cli_args, _) = apply(
+            &config,
+            &Env::default(),
+            &["tagge_rs", "--use-sha", "patch"],
+        );
+        assert!(cli_args.use_sha);
+        assert_eq!(cli_args.bump, Some(VersionBump::Patch));
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let cases = [
+            "use-sha = true\nunknown = 1\n",
+            "use_sha = true\n",
+            "[bump-labels]\nmajor = [\"breaking\"]\nhuge = [\"x\"]\n",
+        ];
+        for contents in cases {
+            assert!(
+                toml::from_str::<FileConfig>(contents).is_err(),
+                "{contents}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_cargo_metadata() {
+        let workdir = std::env::temp_dir().join(format!("tagge-config-{}", std::process::id()));
+        std::fs::create_dir_all(&workdir).unwrap();
+
+        std::fs::write(
+            workdir.join("Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[package.metadata.tagge]\nuse-sha = true\n",
+        )
+        .unwrap();
+        let (path, config) = load_cargo_config(&workdir).unwrap().unwrap();
+        assert_eq!(path, workdir.join("Cargo.toml"));
+        assert_eq!(config.use_sha, Some(true));
+
+        std::fs::write(
+            workdir.join("Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[package.metadata.tagge]\nuse-shas = true\n",
+        )
+        .unwrap();
+        assert!(load_cargo_config(&workdir).is_err());
+
+        std::fs::write(workdir.join(REPO_CONFIG_FILE), "no-such-option = true\n").unwrap();
+        assert!(load_repo_config(&workdir).is_err());
+        std::fs::remove_dir_all(&workdir).unwrap();
+    }
+}
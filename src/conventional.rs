@@ -0,0 +1,215 @@
+use crate::args::CoreBump;
+use git2::Commit;
+
+/// Parsed header (and breaking change footers) of a Conventional Commit.
+/// See: https://www.conventionalcommits.org/en/v1.0.0/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionalCommit {
+    pub kind: String,
+    pub scope: Option<String>,
+    pub breaking: bool,
+    pub description: String,
+}
+
+impl ConventionalCommit {
+    /// Parse a full commit message (summary + body).
+    /// Returns `None` if the summary is not a Conventional Commit header.
+    pub fn parse(message: &str) -> Option<Self> {
+        let mut lines = message.lines();
+        let header = lines.next()?.trim();
+
+        let (prefix, description) = header.split_once(':')?;
+        let description = description.trim();
+        if description.is_empty() {
+            return None;
+        }
+
+        let (prefix, bang) = match prefix.strip_suffix('!') {
+            Some(p) => (p, true),
+            None => (prefix, false),
+        };
+
+        let (kind, scope) = match prefix.split_once('(') {
+            Some((kind, rest)) => {
+                let scope = rest.strip_suffix(')')?;
+                if scope.is_empty() {
+                    return None;
+                }
+                (kind, Some(scope.to_string()))
+            }
+            None => (prefix, None),
+        };
+
+        if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return None;
+        }
+
+        // Footers live in the body, "BREAKING CHANGE" and "BREAKING-CHANGE" are synonyms
+        let footer_breaking = lines.any(|line| {
+            line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:")
+        });
+
+        Some(Self {
+            kind: kind.to_lowercase(),
+            scope,
+            breaking: bang || footer_breaking,
+            description: description.to_string(),
+        })
+    }
+
+    /// Version bump this commit requires on its own, if any.
+    pub fn bump(&self) -> Option<CoreBump> {
+        if self.breaking {
+            Some(CoreBump::Major)
+        } else if self.kind == "feat" {
+            Some(CoreBump::Minor)
+        } else if self.kind == "fix" {
+            Some(CoreBump::Patch)
+        } else {
+            None
+        }
+    }
+}
+
+/// Result of inferring the bump level from a commit range.
+pub struct InferredBump {
+    pub bump: CoreBump,
+    /// Commits that required `bump`, as "<SHA:7> <summary>"
+    pub reasons: Vec<String>,
+}
+
+/// Infer the version bump from Conventional Commits.
+/// Defaults to a patch bump if no commit requires anything else.
+pub fn infer_bump(commits: &[Commit]) -> InferredBump {
+    let mut bump = CoreBump::Patch;
+    let mut reasons = Vec::new();
+
+    for commit in commits {
+        let Some(commit_bump) = commit
+            .message()
+            .and_then(ConventionalCommit::parse)
+            .and_then(|cc| cc.bump())
+        else {
+            continue;
+        };
+
+        if commit_bump > bump {
+            bump = commit_bump;
+            reasons.clear();
+        }
+        if commit_bump == bump {
+            let sha = commit.id().to_string();
+            reasons.push(format!(
+                "{} {}",
+                &sha[..7],
+                commit.summary().unwrap_or_default()
+            ));
+        }
+    }
+
+    InferredBump { bump, reasons }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(
+        kind: &str,
+        scope: Option<&str>,
+        breaking: bool,
+        description: &str,
+    ) -> ConventionalCommit {
+        ConventionalCommit {
+            kind: kind.to_string(),
+            scope: scope.map(str::to_string),
+            breaking,
+            description: description.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_headers() {
+        let cases = [
+            ("feat: Add a flag", cc("feat", None, false, "Add a flag")),
+            (
+                "fix(parser): Handle tabs",
+                cc("fix", Some("parser"), false, "Handle tabs"),
+            ),
+            (
+                "feat!: Drop Node 16",
+                cc("feat", None, true, "Drop Node 16"),
+            ),
+            (
+                "refactor(api)!: Rename",
+                cc("refactor", Some("api"), true, "Rename"),
+            ),
+            ("Feat: Case", cc("feat", None, false, "Case")),
+            ("build-deps: Bump", cc("build-deps", None, false, "Bump")),
+            ("fix: Parse a: b", cc("fix", None, false, "Parse a: b")),
+            ("  docs:   Trim  \n", cc("docs", None, false, "Trim")),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(
+                ConventionalCommit::parse(message),
+                Some(expected),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_breaking_footers() {
+        let cases = [
+            ("feat: A\n\nBREAKING CHANGE: Config moved", true),
+            ("fix: A\n\nBody\n\nBREAKING-CHANGE: Gone", true),
+            ("fix: A\n\nRefs: #12\nBREAKING CHANGE: Gone", true),
+            // Only footer tokens at the start of a line count
+            ("fix: A\n\nNo BREAKING CHANGE: here", false),
+            ("fix: A\n\nbreaking change: lowercase", false),
+            ("fix: BREAKING CHANGE: in the summary", false),
+        ];
+        for (message, breaking) in cases {
+            let parsed = ConventionalCommit::parse(message).unwrap();
+            assert_eq!(parsed.breaking, breaking, "{message}");
+        }
+    }
+
+    #[test]
+    fn parse_non_conforming() {
+        let cases = [
+            "Add a flag",
+            "Merge branch 'main' into feature",
+            "Merge pull request #12 from owner/branch",
+            "feat:",
+            "feat:   ",
+            ": Missing type",
+            "feat(): Empty scope",
+            "feat(scope: Unclosed scope",
+            "feat!(scope): Bang before scope",
+            "feat scope: Space",
+            "",
+        ];
+        for message in cases {
+            assert_eq!(ConventionalCommit::parse(message), None, "{message}");
+        }
+    }
+
+    #[test]
+    fn bump_levels() {
+        let cases = [
+            ("feat: A", Some(CoreBump::Minor)),
+            ("fix: A", Some(CoreBump::Patch)),
+            ("docs: A", None),
+            ("chore!: A", Some(CoreBump::Major)),
+            ("fix: A\n\nBREAKING CHANGE: B", Some(CoreBump::Major)),
+        ];
+        for (message, bump) in cases {
+            assert_eq!(
+                ConventionalCommit::parse(message).unwrap().bump(),
+                bump,
+                "{message}"
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use git2::Config;
+use git2::Cred;
+use git2::CredentialType;
+use git2::RemoteCallbacks;
+use std::cell::RefCell;
+use std::fmt;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+/// Keys in `~/.ssh` tried after the agent and the configured key.
+const DEFAULT_SSH_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Credentials offered to the remote when fetching and pushing.
+#[derive(Clone, Default)]
+pub struct GitAuth {
+    /// HTTPS token with the user name it is sent as
+    pub token: Option<(String, String)>,
+    /// SSH private key from `--ssh-key` or the config
+    pub ssh_key: Option<PathBuf>,
+}
+
+enum Method {
+    SshAgent,
+    SshKey(PathBuf),
+    CredentialHelper,
+    Token,
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SshAgent => write!(f, "ssh-agent"),
+            Self::SshKey(path) => write!(f, "SSH key {}", path.display()),
+            Self::CredentialHelper => write!(f, "git credential helper"),
+            Self::Token => write!(f, "token"),
+        }
+    }
+}
+
+impl GitAuth {
+    /// Callbacks that try every applicable method once, in order, recording each attempt.
+    pub fn callbacks<'a>(
+        &'a self,
+        config: Option<Config>,
+        attempts: &'a RefCell<Vec<String>>,
+    ) -> RemoteCallbacks<'a> {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username_from_url, allowed_types| {
+            let username = username_from_url.unwrap_or("git");
+            // SSH asks for the user name first if the URL has none
+            if allowed_types.contains(CredentialType::USERNAME) {
+                return Cred::username(username);
+            }
+
+            for method in self.methods(allowed_types) {
+                let name = method.to_string();
+                if attempts.borrow().contains(&name) {
+                    continue;
+                }
+                attempts.borrow_mut().push(name);
+
+                tracing::info!("Trying {method} for {url} (user: {username})");
+                let cred = match &method {
+                    Method::SshAgent => Cred::ssh_key_from_agent(username),
+                    Method::SshKey(path) => {
+                        let mut public_key = path.clone().into_os_string();
+                        public_key.push(".pub");
+                        let public_key = PathBuf::from(public_key);
+                        let public_key = public_key.is_file().then_some(public_key.as_path());
+                        Cred::ssh_key(username, public_key, path, None)
+                    }
+                    Method::CredentialHelper => match &config {
+                        Some(config) => Cred::credential_helper(config, url, username_from_url),
+                        None => Err(git2::Error::from_str("git config not available")),
+                    },
+                    Method::Token => {
+                        let (user, token) = self.token.as_ref().expect("Only offered with a token");
+                        Cred::userpass_plaintext(user, token)
+                    }
+                };
+                match cred {
+                    Ok(cred) => return Ok(cred),
+                    Err(e) => tracing::warn!("{method} failed: {}", e.message()),
+                }
+            }
+
+            Err(git2::Error::from_str(&format!(
+                "All credentials failed for {url}, tried: {}",
+                attempts.borrow().join(", ")
+            )))
+        });
+        callbacks
+    }
+
+    fn methods(&self, allowed_types: CredentialType) -> Vec<Method> {
+        let mut methods = Vec::new();
+        if allowed_types.contains(CredentialType::SSH_KEY) {
+            methods.push(Method::SshAgent);
+            methods.extend(self.ssh_key.clone().map(Method::SshKey));
+            if let Some(ssh_dir) = dirs::home_dir().map(|home| home.join(".ssh")) {
+                methods.extend(
+                    DEFAULT_SSH_KEYS
+                        .iter()
+                        .map(|name| ssh_dir.join(name))
+                        .filter(|path| path.is_file())
+                        .map(Method::SshKey),
+                );
+            }
+        }
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            methods.push(Method::CredentialHelper);
+            if self.token.is_some() {
+                methods.push(Method::Token);
+            }
+        }
+        methods
+    }
+}
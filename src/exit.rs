@@ -0,0 +1,53 @@
+/// Outcomes a release pipeline can branch on, besides success (0), any other
+/// error (1) and invalid arguments (2, from clap).
+///
+/// Errors carry the diagnostic code of their outcome, e.g.
+/// `miette!(code = Exit::NoTags.code(), "No tags found!")`, which selects the exit code.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Exit {
+    NoCommits,
+    NoTags,
+    MissingToken,
+    TagExists,
+    /// A question was answered with no
+    Aborted,
+}
+
+impl Exit {
+    const ALL: [Self; 5] = [
+        Self::NoCommits,
+        Self::NoTags,
+        Self::MissingToken,
+        Self::TagExists,
+        Self::Aborted,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NoCommits => "tagge::no_commits",
+            Self::NoTags => "tagge::no_tags",
+            Self::MissingToken => "tagge::missing_token",
+            Self::TagExists => "tagge::tag_exists",
+            Self::Aborted => "tagge::aborted",
+        }
+    }
+
+    fn status(&self) -> u8 {
+        match self {
+            Self::NoCommits => 3,
+            Self::NoTags => 4,
+            Self::MissingToken => 5,
+            Self::TagExists => 6,
+            Self::Aborted => 7,
+        }
+    }
+
+    /// Exit code for an error, 1 unless it has the code of an outcome.
+    pub fn status_of(report: &miette::Report) -> u8 {
+        let code = report.code().map(|code| code.to_string());
+        Self::ALL
+            .iter()
+            .find(|exit| code.as_deref() == Some(exit.code()))
+            .map_or(1, Self::status)
+    }
+}
@@ -0,0 +1,586 @@
+use crate::assets::ReleaseAsset;
+use crate::gitea;
+use crate::github;
+use crate::gitlab;
+use crate::output::say;
+use crate::remote_url;
+use crate::remote_url::RemoteUrl;
+use chrono::Utc;
+use colored::Colorize;
+use futures::StreamExt;
+use futures::stream;
+use git2::Repository;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Write as FmtWrite;
+use std::future::Future;
+use std::time::Duration;
+
+/// Code hosting platforms tagge_rs can look up PRs on and publish releases to.
+#[derive(Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeKind {
+    GitHub,
+    GitLab,
+    Gitea,
+    Forgejo,
+}
+
+impl ForgeKind {
+    /// Detect the forge from well-known host names.
+    fn detect(host: &str) -> Option<Self> {
+        match host {
+            "github.com" => Some(Self::GitHub),
+            "gitlab.com" => Some(Self::GitLab),
+            "gitea.com" => Some(Self::Gitea),
+            "codeberg.org" => Some(Self::Forgejo),
+            _ => None,
+        }
+    }
+
+    /// Environment variable holding the API token.
+    pub fn token_env(&self) -> &'static str {
+        match self {
+            Self::GitHub => "GH_TOKEN",
+            Self::GitLab => "GITLAB_TOKEN",
+            Self::Gitea => "GITEA_TOKEN",
+            Self::Forgejo => "FORGEJO_TOKEN",
+        }
+    }
+
+    /// User name sent with the token when fetching or pushing over HTTPS.
+    pub fn git_username(&self) -> &'static str {
+        match self {
+            Self::GitHub => "x-access-token",
+            Self::GitLab => "oauth2",
+            // Any user name is accepted with a token as the password
+            Self::Gitea | Self::Forgejo => "git",
+        }
+    }
+
+    /// What the forge calls a pull request.
+    pub fn pr_term(&self) -> &'static str {
+        match self {
+            Self::GitLab => "MR",
+            Self::GitHub | Self::Gitea | Self::Forgejo => "PR",
+        }
+    }
+
+    /// `GITHUB_API_URL` is set by GitHub Actions, also on GitHub Enterprise runners.
+    fn api_url_from_env(&self) -> Option<String> {
+        match self {
+            Self::GitHub => std::env::var("GITHUB_API_URL").ok(),
+            _ => None,
+        }
+    }
+
+    fn default_api_url(&self, host: &str) -> String {
+        match self {
+            Self::GitHub if host == "github.com" => "https://api.github.com".to_string(),
+            // GitHub Enterprise Server
+            Self::GitHub => format!("https://{host}/api/v3"),
+            Self::GitLab => format!("https://{host}/api/v4"),
+            Self::Gitea | Self::Forgejo => format!("https://{host}/api/v1"),
+        }
+    }
+}
+
+impl Display for ForgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::GitHub => "GitHub",
+            Self::GitLab => "GitLab",
+            Self::Gitea => "Gitea",
+            Self::Forgejo => "Forgejo",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Forge of a self-hosted domain, from `[forge-hosts]` in the config.
+/// Either just the type (`"gitlab"`) or a table with an `api-url`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "ForgeHostDef")]
+pub struct ForgeHost {
+    pub kind: ForgeKind,
+    pub api_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ForgeHostDef {
+    Kind(ForgeKind),
+    #[serde(rename_all = "kebab-case")]
+    Full {
+        #[serde(rename = "type")]
+        kind: ForgeKind,
+        api_url: Option<String>,
+    },
+}
+
+impl From<ForgeHostDef> for ForgeHost {
+    fn from(def: ForgeHostDef) -> Self {
+        match def {
+            ForgeHostDef::Kind(kind) => Self {
+                kind,
+                api_url: None,
+            },
+            ForgeHostDef::Full { kind, api_url } => Self { kind, api_url },
+        }
+    }
+}
+
+impl Display for ForgeHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.api_url {
+            Some(api_url) => write!(f, "{} ({api_url})", self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+pub type ForgeHosts = BTreeMap<String, ForgeHost>;
+
+/// A pull request (or merge request) linked to a commit.
+#[derive(Debug, Clone)]
+pub struct PrInfo {
+    pub number: u64,
+    /// How the forge refers to it, e.g. `#12` or `!12`
+    pub reference: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub labels: Vec<String>,
+}
+
+/// Options for the release created alongside the tag.
+pub struct ReleaseOptions<'a> {
+    pub tag_name: &'a str,
+    pub body: &'a str,
+    pub draft: bool,
+    pub prerelease: bool,
+}
+
+impl<'a> ReleaseOptions<'a> {
+    /// Tags of versions with a pre-release part, e.g. `v1.2.0-rc.1`, are always pre-releases.
+    pub fn new(tag_name: &'a str, body: &'a str, draft: bool, prerelease: bool) -> Self {
+        let prerelease = prerelease
+            || semver::Version::parse(tag_name.trim_start_matches('v'))
+                .is_ok_and(|version| !version.pre.is_empty());
+        Self {
+            tag_name,
+            body,
+            draft,
+            prerelease,
+        }
+    }
+}
+
+/// A release created on the forge.
+pub enum Release {
+    GitHub(Box<octocrab::models::repos::Release>),
+    GitLab { url: String },
+    Gitea { url: String },
+}
+
+impl Release {
+    pub fn url(&self) -> &str {
+        match self {
+            Self::GitHub(release) => release.html_url.as_str(),
+            Self::GitLab { url } | Self::Gitea { url } => url,
+        }
+    }
+}
+
+/// A repository on a forge, resolved from a git remote.
+#[derive(Debug, Clone)]
+pub struct ForgeRepo {
+    pub kind: ForgeKind,
+    /// Host of the web interface, with the port of http(s) remotes
+    pub authority: String,
+    /// User, organization or (nested) group
+    pub owner: String,
+    pub name: String,
+    pub api_url: String,
+}
+
+impl ForgeRepo {
+    /// Resolve the forge of a git remote. libgit2 has already applied any
+    /// `url.<base>.insteadOf` rewrites to the remote URL.
+    pub fn from_remote(repo: &Repository, remote: &str, hosts: &ForgeHosts) -> MietteResult<Self> {
+        let binding = repo
+            .find_remote(remote)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not find git remote {remote}!"))?;
+        let url = binding.url().ok_or_else(|| miette!("No url!"))?;
+
+        let RemoteUrl {
+            host,
+            http_port,
+            owner,
+            name,
+        } = remote_url::parse(url).ok_or_else(|| {
+            miette!("Failed to get the host, owner and repo name from remote {remote}: {url}")
+        })?;
+        let authority = match http_port {
+            Some(port) => format!("{host}:{port}"),
+            None => host.clone(),
+        };
+
+        let configured = hosts.get(&host);
+        let kind = configured
+            .map(|h| h.kind)
+            .or_else(|| ForgeKind::detect(&host))
+            .ok_or_else(|| {
+                miette!(
+                    help = format!(
+                        "Add the host to the config, e.g. [forge-hosts] \"{host}\" = \"gitlab\""
+                    ),
+                    "Unknown forge host {host}"
+                )
+            })?;
+        let api_url = configured
+            .and_then(|h| h.api_url.clone())
+            .or_else(|| kind.api_url_from_env())
+            .unwrap_or_else(|| kind.default_api_url(&authority));
+
+        Ok(Self {
+            kind,
+            authority,
+            owner,
+            name,
+            api_url: api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub fn web_url(&self) -> String {
+        format!("https://{}/{}/{}", self.authority, self.owner, self.name)
+    }
+
+    /// Web page comparing two revisions.
+    pub fn compare_url(&self, from: &str, to: &str) -> String {
+        match self.kind {
+            ForgeKind::GitHub | ForgeKind::Gitea | ForgeKind::Forgejo => {
+                format!("{}/compare/{from}...{to}", self.web_url())
+            }
+            ForgeKind::GitLab => format!("{}/-/compare/{from}...{to}", self.web_url()),
+        }
+    }
+
+    /// Web page of the release of a tag.
+    pub fn release_url(&self, tag: &str) -> String {
+        match self.kind {
+            ForgeKind::GitHub | ForgeKind::Gitea | ForgeKind::Forgejo => {
+                format!("{}/releases/tag/{tag}", self.web_url())
+            }
+            ForgeKind::GitLab => format!("{}/-/releases/{tag}", self.web_url()),
+        }
+    }
+
+    /// The API token, from the command line or the forge's environment variable.
+    pub fn token(&self, cli_token: Option<String>) -> Option<String> {
+        cli_token.or_else(|| std::env::var(self.kind.token_env()).ok())
+    }
+
+    /// Find the PR for each commit.
+    pub async fn fetch_prs(
+        &self,
+        token: &str,
+        commit_shas: impl Iterator<Item = String>,
+    ) -> MietteResult<Vec<(String, Option<PrInfo>)>> {
+        match self.kind {
+            ForgeKind::GitHub => github::fetch_prs(self, token, commit_shas).await,
+            ForgeKind::GitLab => gitlab::fetch_prs(self, token, commit_shas).await,
+            ForgeKind::Gitea | ForgeKind::Forgejo => {
+                gitea::fetch_prs(self, token, commit_shas).await
+            }
+        }
+    }
+
+    pub async fn create_release(
+        &self,
+        token: &str,
+        options: &ReleaseOptions<'_>,
+    ) -> MietteResult<Release> {
+        match self.kind {
+            ForgeKind::GitHub => github::create_release(self, token, options)
+                .await
+                .map(|release| Release::GitHub(Box::new(release))),
+            ForgeKind::GitLab => gitlab::create_release(self, token, options).await,
+            ForgeKind::Gitea | ForgeKind::Forgejo => {
+                gitea::create_release(self, token, options).await
+            }
+        }
+    }
+
+    /// Upload the assets to the release, returns the name and download URL of each.
+    pub async fn upload_assets(
+        &self,
+        token: &str,
+        release: &Release,
+        assets: &[ReleaseAsset],
+    ) -> MietteResult<Vec<(String, String)>> {
+        match release {
+            Release::GitHub(release) => {
+                let uploaded = github::upload_assets(self, token, release, assets).await?;
+                Ok(uploaded
+                    .into_iter()
+                    .map(|a| (a.name, a.browser_download_url.to_string()))
+                    .collect())
+            }
+            Release::GitLab { .. } | Release::Gitea { .. } => {
+                Err(miette!("Release assets are only supported on GitHub"))
+            }
+        }
+    }
+
+    pub fn supports_assets(&self) -> bool {
+        self.kind == ForgeKind::GitHub
+    }
+}
+
+/// Build an HTTP client for the REST API of a forge,
+/// authenticating with `token` in the `auth_header` header.
+pub fn http_client(auth_header: &'static str, token: &str) -> MietteResult<reqwest::Client> {
+    // reqwest shares rustls with octocrab, which uses the ring provider.
+    // Installing fails harmlessly if it is already installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    let mut token = reqwest::header::HeaderValue::from_str(token)
+        .into_diagnostic()
+        .wrap_err("Invalid API token")?;
+    token.set_sensitive(true);
+    headers.insert(auth_header, token);
+
+    reqwest::Client::builder()
+        .user_agent(concat!("tagge_rs/", env!("CARGO_PKG_VERSION")))
+        .default_headers(headers)
+        .build()
+        .into_diagnostic()
+}
+
+/// Concurrent requests of per-commit PR lookups.
+const CONCURRENCY: usize = 8;
+const ATTEMPTS: u32 = 3;
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(300);
+
+/// Look up the PR of each commit with `lookup`, a few at a time, in commit order.
+/// Failed lookups are counted in a warning and the commit is shown without a PR.
+pub async fn fetch_prs_per_commit<F, Fut>(
+    kind: ForgeKind,
+    commit_shas: impl Iterator<Item = String>,
+    lookup: F,
+) -> Vec<(String, Option<PrInfo>)>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = MietteResult<Option<PrInfo>>>,
+{
+    let commit_shas: Vec<String> = commit_shas.collect();
+    let term = kind.pr_term();
+    tracing::info!(
+        "Looking up {term}s for {} commits, at most {CONCURRENCY} at a time",
+        commit_shas.len()
+    );
+    let results: Vec<_> = stream::iter(commit_shas)
+        .map(|sha| {
+            let lookup = lookup(sha.clone());
+            async move { (sha, lookup.await) }
+        })
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut failed = 0;
+    let commit_prs = results
+        .into_iter()
+        .map(|(sha, result)| match result {
+            Ok(pr) => (sha, pr),
+            Err(e) => {
+                tracing::error!("Failed to fetch {term}s for commit {sha}: {e:?}");
+                failed += 1;
+                (sha, None)
+            }
+        })
+        .collect();
+
+    if failed > 0 {
+        say!(
+            "{}",
+            format!("Warning: Failed to look up {term}s for {failed} commit(s), shown as (N/A)!\n")
+                .yellow()
+        );
+    }
+    commit_prs
+}
+
+/// Sleep until a rate limit resets, unless that takes too long.
+pub async fn wait_for_rate_limit(kind: ForgeKind, wait: Duration) -> MietteResult<()> {
+    if wait > MAX_RATE_LIMIT_WAIT {
+        return Err(miette!(
+            "{kind} rate limit resets in {}s, not waiting that long",
+            wait.as_secs()
+        ));
+    }
+    tracing::warn!("Waiting {}s for the {kind} rate limit", wait.as_secs());
+    tokio::time::sleep(wait).await;
+    Ok(())
+}
+
+/// Send a request, waiting and retrying when rate limited or on server errors.
+/// Returns `None` if the API responds with 404 Not Found.
+pub async fn send_with_retry(
+    kind: ForgeKind,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> MietteResult<Option<reqwest::Response>> {
+    let mut attempt = 1;
+    loop {
+        let response = request().send().await.into_diagnostic()?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(Some(response));
+        }
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let header = |name: &str| -> Option<u64> {
+            response.headers().get(name)?.to_str().ok()?.parse().ok()
+        };
+        // GitHub answers 403 when over its rate limits, with the headers telling them apart
+        let rate_limited = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || (status == reqwest::StatusCode::FORBIDDEN
+                && (header("retry-after").is_some() || header("x-ratelimit-remaining") == Some(0)));
+        if !(rate_limited || status.is_server_error()) || attempt >= ATTEMPTS {
+            let body = response.text().await.unwrap_or_default();
+            return Err(miette!("{kind} API returned {status}: {body}"));
+        }
+
+        let retry_after = header("retry-after").map(Duration::from_secs);
+        if rate_limited {
+            tracing::warn!("Rate limited by {kind} (attempt {attempt}/{ATTEMPTS})");
+            // Secondary rate limits send retry-after, primary ones the reset time
+            let reset_in = header("x-ratelimit-reset").map(|reset| {
+                Duration::from_secs(reset.saturating_sub(Utc::now().timestamp() as u64))
+            });
+            let wait = retry_after
+                .or(reset_in)
+                .unwrap_or(Duration::from_secs(2u64.pow(attempt) * 30));
+            wait_for_rate_limit(kind, wait).await?;
+        } else {
+            let wait = retry_after
+                .unwrap_or(Duration::from_secs(2u64.pow(attempt)))
+                .min(MAX_RATE_LIMIT_WAIT);
+            tracing::warn!(
+                "{kind} API returned {status} (attempt {attempt}/{ATTEMPTS}), retrying in {}s",
+                wait.as_secs()
+            );
+            tokio::time::sleep(wait).await;
+        }
+        attempt += 1;
+    }
+}
+
+/// Percent-encode a URL path segment or query value.
+pub fn encode_component(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{byte:02X}").expect("Should never fail"),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+
+    /// Serve `statuses` in order, then 200 OK.
+    async fn server_responding(statuses: &[u16]) -> MockServer {
+        let server = MockServer::start().await;
+        for status in statuses {
+            Mock::given(method("GET"))
+                .and(path("/resource"))
+                .respond_with(ResponseTemplate::new(*status).insert_header("retry-after", "0"))
+                .up_to_n_times(1)
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/resource"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    async fn send(server: &MockServer) -> MietteResult<Option<reqwest::Response>> {
+        let client = http_client("PRIVATE-TOKEN", "token").unwrap();
+        let url = format!("{}/resource", server.uri());
+        send_with_retry(ForgeKind::GitLab, || client.get(&url)).await
+    }
+
+    #[test]
+    fn release_options_prerelease() {
+        // (tag, --prerelease, expected prerelease)
+        let cases = [
+            ("v1.2.0", false, false),
+            ("v1.2.0", true, true),
+            ("v1.2.0-rc.1", false, true),
+            ("1.2.0-beta.3", false, true),
+            ("v1.2.0+build.1", false, false),
+            ("release-1", false, false),
+        ];
+        for (tag_name, prerelease, expected) in cases {
+            let options = ReleaseOptions::new(tag_name, "", false, prerelease);
+            assert_eq!(options.prerelease, expected, "{tag_name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn send_with_retry_retries_rate_limits_and_server_errors() {
+        for statuses in [&[429][..], &[403], &[503], &[500, 429]] {
+            let server = server_responding(statuses).await;
+            let response = send(&server).await.unwrap().expect("Should not be 404");
+            assert_eq!(response.text().await.unwrap(), "ok", "after {statuses:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn send_with_retry_gives_up() {
+        let server = server_responding(&[429, 502, 503]).await;
+        let error = send(&server).await.unwrap_err();
+        assert!(error.to_string().contains("503"), "{error}");
+
+        // Client errors are not retried, 403 only is when it comes with rate limit headers
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/resource"))
+            .respond_with(ResponseTemplate::new(403))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let error = send(&server).await.unwrap_err();
+        assert!(error.to_string().contains("403"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn send_with_retry_not_found() {
+        let server = server_responding(&[404]).await;
+        assert!(send(&server).await.unwrap().is_none());
+    }
+}
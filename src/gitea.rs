@@ -0,0 +1,284 @@
+use crate::forge::ForgeRepo;
+use crate::forge::PrInfo;
+use crate::forge::Release;
+use crate::forge::ReleaseOptions;
+use crate::forge::fetch_prs_per_commit;
+use crate::forge::http_client;
+use crate::forge::send_with_retry;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use serde::Deserialize;
+
+fn client(token: &str) -> MietteResult<reqwest::Client> {
+    http_client("Authorization", &format!("token {token}"))
+}
+
+fn repo_url(forge: &ForgeRepo) -> String {
+    format!("{}/repos/{}/{}", forge.api_url, forge.owner, forge.name)
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    number: u64,
+    title: String,
+    user: Option<User>,
+    #[serde(default)]
+    labels: Vec<Label>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
+
+impl From<PullRequest> for PrInfo {
+    fn from(pr: PullRequest) -> Self {
+        Self {
+            number: pr.number,
+            reference: format!("#{}", pr.number),
+            title: pr.title,
+            author: pr.user.map(|u| u.login),
+            labels: pr.labels.into_iter().map(|l| l.name).collect(),
+        }
+    }
+}
+
+/// Find the PR for each commit on Gitea or Forgejo.
+pub async fn fetch_prs(
+    forge: &ForgeRepo,
+    token: &str,
+    commit_shas: impl Iterator<Item = String>,
+) -> MietteResult<Vec<(String, Option<PrInfo>)>> {
+    let client = client(token)?;
+    let repo_url = repo_url(forge);
+    Ok(fetch_prs_per_commit(forge.kind, commit_shas, |sha| {
+        let client = &client;
+        let url = format!("{repo_url}/commits/{sha}/pull");
+        async move { fetch_commit_pr(forge, client, &url, &sha).await }
+    })
+    .await)
+}
+
+async fn fetch_commit_pr(
+    forge: &ForgeRepo,
+    client: &reqwest::Client,
+    url: &str,
+    sha: &str,
+) -> MietteResult<Option<PrInfo>> {
+    tracing::debug!("Fetching PR for commit: {sha}");
+    // The API answers 404 if the commit was not merged through a PR
+    let Some(response) = send_with_retry(forge.kind, || client.get(url)).await? else {
+        tracing::info!("No PRs found for commit {sha}");
+        return Ok(None);
+    };
+    let pr: PullRequest = response.json().await.into_diagnostic()?;
+    tracing::info!("Found PR #{} for commit {sha}", pr.number);
+    Ok(Some(pr.into()))
+}
+
+#[derive(Deserialize)]
+struct CreatedRelease {
+    html_url: String,
+}
+
+pub async fn create_release(
+    forge: &ForgeRepo,
+    token: &str,
+    options: &ReleaseOptions<'_>,
+) -> MietteResult<Release> {
+    let client = client(token)?;
+    tracing::info!(
+        "Creating {} release {} (draft: {}, prerelease: {})",
+        forge.kind,
+        options.tag_name,
+        options.draft,
+        options.prerelease
+    );
+
+    let url = format!("{}/releases", repo_url(forge));
+    let body = serde_json::json!({
+        "tag_name": options.tag_name,
+        "name": options.tag_name,
+        "body": options.body,
+        "draft": options.draft,
+        "prerelease": options.prerelease,
+    });
+    let response = send_with_retry(forge.kind, || client.post(&url).json(&body))
+        .await
+        .and_then(|response| {
+            response.ok_or_else(|| {
+                miette!(
+                    "{} repository {}/{} not found",
+                    forge.kind,
+                    forge.owner,
+                    forge.name
+                )
+            })
+        })
+        .wrap_err_with(|| {
+            format!(
+                "Failed to create {} release {}",
+                forge.kind, options.tag_name
+            )
+        })?;
+    let created: CreatedRelease = response.json().await.into_diagnostic()?;
+
+    Ok(Release::Gitea {
+        url: created.html_url,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::ForgeKind;
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+    use wiremock::matchers::body_json;
+    use wiremock::matchers::header;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+
+    fn forge(server: &MockServer, kind: ForgeKind) -> ForgeRepo {
+        ForgeRepo {
+            kind,
+            authority: "codeberg.org".to_string(),
+            owner: "owner".to_string(),
+            name: "repo".to_string(),
+            api_url: server.uri(),
+        }
+    }
+
+    fn pr(number: u64) -> serde_json::Value {
+        json!({
+            "number": number,
+            "title": format!("PR {number}"),
+            "user": { "login": "dev" },
+            "labels": [{ "name": "bug" }],
+        })
+    }
+
+    async fn mock_commit_pr(server: &MockServer, sha: &str, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path(format!("/repos/owner/repo/commits/{sha}/pull")))
+            .and(header("Authorization", "token secret"))
+            .respond_with(response)
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_in_commit_order() {
+        let server = MockServer::start().await;
+        // The slowest lookup comes first and must stay first
+        let slow = ResponseTemplate::new(200)
+            .set_body_json(pr(1))
+            .set_delay(Duration::from_millis(200));
+        mock_commit_pr(&server, "aaa", slow).await;
+        mock_commit_pr(&server, "bbb", ResponseTemplate::new(404)).await;
+        mock_commit_pr(
+            &server,
+            "ccc",
+            ResponseTemplate::new(200).set_body_json(pr(3)),
+        )
+        .await;
+
+        let shas = ["aaa", "bbb", "ccc"].map(String::from);
+        let prs = fetch_prs(
+            &forge(&server, ForgeKind::Forgejo),
+            "secret",
+            shas.into_iter(),
+        )
+        .await
+        .unwrap();
+
+        let found: Vec<_> = prs
+            .iter()
+            .map(|(sha, pr)| (sha.as_str(), pr.as_ref().map(|pr| pr.reference.as_str())))
+            .collect();
+        assert_eq!(
+            found,
+            [("aaa", Some("#1")), ("bbb", None), ("ccc", Some("#3"))]
+        );
+        let pr = prs[0].1.as_ref().unwrap();
+        assert_eq!(pr.title, "PR 1");
+        assert_eq!(pr.author.as_deref(), Some("dev"));
+        assert_eq!(pr.labels, ["bug"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_retries_rate_limits() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/commits/aaa/pull"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        mock_commit_pr(
+            &server,
+            "aaa",
+            ResponseTemplate::new(200).set_body_json(pr(1)),
+        )
+        .await;
+
+        let prs = fetch_prs(
+            &forge(&server, ForgeKind::Gitea),
+            "secret",
+            [String::from("aaa")].into_iter(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(prs[0].1.as_ref().map(|pr| pr.number), Some(1));
+    }
+
+    #[tokio::test]
+    async fn create_release_body() {
+        let server = MockServer::start().await;
+        let html_url = "https://codeberg.org/owner/repo/releases/tag/v1.2.0";
+        Mock::given(method("POST"))
+            .and(path("/repos/owner/repo/releases"))
+            .and(header("Authorization", "token secret"))
+            .and(body_json(json!({
+                "tag_name": "v1.2.0",
+                "name": "v1.2.0",
+                "body": "- Notes",
+                "draft": true,
+                "prerelease": true,
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "html_url": html_url })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let options = ReleaseOptions::new("v1.2.0", "- Notes", true, true);
+        let release = create_release(&forge(&server, ForgeKind::Forgejo), "secret", &options)
+            .await
+            .unwrap();
+        assert_eq!(release.url(), html_url);
+    }
+
+    #[tokio::test]
+    async fn create_release_in_unknown_repository() {
+        let server = MockServer::start().await;
+        let options = ReleaseOptions::new("v1.2.0", "- Notes", false, false);
+        let Err(error) =
+            create_release(&forge(&server, ForgeKind::Gitea), "secret", &options).await
+        else {
+            panic!("Should fail without the repository");
+        };
+        assert!(format!("{error:?}").contains("not found"), "{error:?}");
+    }
+}
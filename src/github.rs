@@ -0,0 +1,894 @@
+use crate::assets;
+use crate::assets::CHECKSUMS_NAME;
+use crate::assets::ReleaseAsset;
+use crate::forge::ForgeKind;
+use crate::forge::ForgeRepo;
+use crate::forge::PrInfo;
+use crate::forge::ReleaseOptions;
+use crate::forge::encode_component;
+use crate::forge::fetch_prs_per_commit;
+use crate::forge::http_client;
+use crate::forge::send_with_retry;
+use crate::forge::wait_for_rate_limit;
+use chrono::Utc;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use octocrab::FromResponse;
+use octocrab::Octocrab;
+use octocrab::models::repos::Asset;
+use octocrab::models::repos::Release;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::time::Duration;
+
+/// Build an authenticated GitHub client for the API at `base_uri`.
+///
+/// Octocrab prefixes every request path with the path of the base URI, so the
+/// GraphQL and upload endpoints of GitHub Enterprise need clients of their own.
+fn client(token: &str, base_uri: &str) -> MietteResult<Octocrab> {
+    tracing::debug!("Using GitHub API at {base_uri}");
+    Octocrab::builder()
+        .personal_token(token)
+        .base_uri(base_uri)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid GitHub API url: {base_uri}"))?
+        .build()
+        .into_diagnostic()
+}
+
+/// GitHub Enterprise serves GraphQL at `/api/graphql`, next to the REST API at `/api/v3`.
+fn graphql_base_uri(api_url: &str) -> &str {
+    api_url.strip_suffix("/v3").unwrap_or(api_url)
+}
+
+/// Scheme and host of an absolute URL, e.g. `https://uploads.github.com`.
+fn origin(url: &str) -> &str {
+    let host_start = url.find("://").map_or(0, |idx| idx + 3);
+    match url[host_start..].find('/') {
+        Some(idx) => &url[..host_start + idx],
+        None => url,
+    }
+}
+
+/// Find the PR for each commit.
+///
+/// Commits are resolved in batches through the GraphQL API. Batches that fail fall back
+/// to the per-commit REST lookups shared with the other forges.
+pub async fn fetch_prs(
+    forge: &ForgeRepo,
+    token: &str,
+    commit_shas: impl Iterator<Item = String>,
+) -> MietteResult<Vec<(String, Option<PrInfo>)>> {
+    let (owner, repo_name) = (forge.owner.as_str(), forge.name.as_str());
+    let graphql = client(token, graphql_base_uri(&forge.api_url))?;
+    let commit_shas: Vec<String> = commit_shas.collect();
+
+    tracing::info!("Starting PR fetching for {} commits", commit_shas.len());
+
+    let mut found = HashMap::with_capacity(commit_shas.len());
+    let mut rest_shas = Vec::new();
+    let mut graphql_wait = None;
+    let mut graphql_exhausted = false;
+    for batch in commit_shas.chunks(GRAPHQL_BATCH_SIZE) {
+        // Only wait for the GraphQL budget when there is another batch to send
+        if let Some(wait) = graphql_wait.take()
+            && let Err(e) = wait_for_rate_limit(ForgeKind::GitHub, wait).await
+        {
+            tracing::warn!("{e}. Using REST for the remaining commits");
+            graphql_exhausted = true;
+        }
+        let result = if graphql_exhausted {
+            Err(miette!("GraphQL rate limit exhausted"))
+        } else {
+            fetch_prs_graphql(&graphql, owner, repo_name, batch).await
+        };
+        match result {
+            Ok((prs, wait)) => {
+                found.extend(prs);
+                graphql_wait = wait;
+            }
+            Err(e) => {
+                tracing::warn!("GraphQL PR lookup failed: {e:?}. Falling back to REST");
+                rest_shas.extend_from_slice(batch);
+            }
+        }
+    }
+
+    if !rest_shas.is_empty() {
+        let client = http_client("Authorization", &format!("Bearer {token}"))?;
+        let repo_url = format!("{}/repos/{owner}/{repo_name}", forge.api_url);
+        let rest_prs = fetch_prs_per_commit(ForgeKind::GitHub, rest_shas.into_iter(), |sha| {
+            let client = &client;
+            let url = format!("{repo_url}/commits/{sha}/pulls");
+            async move { fetch_commit_prs_rest(client, &url, &sha).await }
+        })
+        .await;
+        found.extend(rest_prs);
+    }
+
+    let commit_prs: Vec<_> = commit_shas
+        .into_iter()
+        .map(|sha| {
+            let pr = found.remove(&sha).flatten();
+            (sha, pr)
+        })
+        .collect();
+    tracing::info!("Found {} PR associations", commit_prs.len());
+
+    Ok(commit_prs)
+}
+
+const GRAPHQL_BATCH_SIZE: usize = 50;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQlCommit {
+    associated_pull_requests: GraphQlConnection,
+}
+
+#[derive(Deserialize)]
+struct GraphQlConnection {
+    nodes: Vec<GraphQlPullRequest>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlPullRequest {
+    number: u64,
+    state: String,
+    title: String,
+    author: Option<GraphQlAuthor>,
+    labels: GraphQlLabels,
+}
+
+#[derive(Deserialize)]
+struct GraphQlAuthor {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct GraphQlLabels {
+    nodes: Vec<GraphQlLabel>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlLabel {
+    name: String,
+}
+
+impl From<GraphQlPullRequest> for PrInfo {
+    fn from(pr: GraphQlPullRequest) -> Self {
+        Self {
+            number: pr.number,
+            reference: format!("#{}", pr.number),
+            title: pr.title,
+            author: pr.author.map(|a| a.login),
+            labels: pr.labels.nodes.into_iter().map(|l| l.name).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RestPullRequest {
+    number: u64,
+    state: String,
+    title: String,
+    user: Option<RestUser>,
+    #[serde(default)]
+    labels: Vec<RestLabel>,
+}
+
+#[derive(Deserialize)]
+struct RestUser {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct RestLabel {
+    name: String,
+}
+
+impl From<RestPullRequest> for PrInfo {
+    fn from(pr: RestPullRequest) -> Self {
+        Self {
+            number: pr.number,
+            reference: format!("#{}", pr.number),
+            title: pr.title,
+            author: pr.user.map(|u| u.login),
+            labels: pr.labels.into_iter().map(|l| l.name).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQlRateLimit {
+    cost: u64,
+    remaining: u64,
+    reset_at: String,
+}
+
+/// Look up the PRs of a batch of commits, with the time to wait before the next batch
+/// if the remaining rate limit budget does not cover it.
+async fn fetch_prs_graphql(
+    octocrab: &Octocrab,
+    owner: &str,
+    repo_name: &str,
+    commit_shas: &[String],
+) -> MietteResult<(Vec<(String, Option<PrInfo>)>, Option<Duration>)> {
+    // One aliased object lookup per commit: c0: object(oid: "...") { ... }
+    let mut query = String::from(
+        "query($owner: String!, $name: String!) {\n  rateLimit { cost remaining resetAt }\n  repository(owner: $owner, name: $name) {\n",
+    );
+    for (i, sha) in commit_shas.iter().enumerate() {
+        writeln!(
+            query,
+            "    c{i}: object(oid: \"{sha}\") {{ ... on Commit {{ associatedPullRequests(first: 5) {{ nodes {{ number state title author {{ login }} labels(first: 20) {{ nodes {{ name }} }} }} }} }} }}"
+        )
+        .expect("Should never fail");
+    }
+    query.push_str("  }\n}");
+
+    tracing::debug!("Fetching PRs for {} commits via GraphQL", commit_shas.len());
+    let response: serde_json::Value = octocrab
+        .graphql(&serde_json::json!({
+            "query": query,
+            "variables": { "owner": owner, "name": repo_name },
+        }))
+        .await
+        .into_diagnostic()?;
+
+    if let Some(errors) = response.get("errors") {
+        return Err(miette!("GraphQL errors: {errors}"));
+    }
+    let data = response
+        .get("data")
+        .ok_or_else(|| miette!("GraphQL response has no data"))?;
+
+    let repository = data
+        .get("repository")
+        .ok_or_else(|| miette!("GraphQL response has no repository"))?;
+    let mut commit_prs = Vec::with_capacity(commit_shas.len());
+    for (i, sha) in commit_shas.iter().enumerate() {
+        // Unknown commits resolve to null
+        let commit: Option<GraphQlCommit> = repository
+            .get(format!("c{i}"))
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()
+            .into_diagnostic()?
+            .flatten();
+        let mut pulls = commit
+            .map(|c| c.associated_pull_requests.nodes)
+            .unwrap_or_default();
+
+        // Prefer merged, then closed, then open PRs
+        let pr = ["MERGED", "CLOSED", "OPEN"]
+            .iter()
+            .find_map(|state| pulls.iter().position(|pr| pr.state == *state))
+            .map(|idx| PrInfo::from(pulls.swap_remove(idx)));
+        match &pr {
+            Some(pr) => tracing::info!("Found PR #{} for commit {sha}", pr.number),
+            None => tracing::info!("No PRs found for commit {sha}"),
+        }
+        commit_prs.push((sha.clone(), pr));
+    }
+    Ok((commit_prs, rate_limit_wait(data)))
+}
+
+/// Time until the rate limit resets, if the next batch would not fit in the remaining budget.
+fn rate_limit_wait(data: &serde_json::Value) -> Option<Duration> {
+    let rate_limit: GraphQlRateLimit = serde_json::from_value(data.get("rateLimit")?.clone())
+        .inspect_err(|e| tracing::warn!("Invalid GraphQL rate limit: {e}"))
+        .ok()?;
+    tracing::info!(
+        "GraphQL rate limit: cost {}, remaining {}",
+        rate_limit.cost,
+        rate_limit.remaining
+    );
+    if rate_limit.remaining >= rate_limit.cost {
+        return None;
+    }
+    let reset_at = chrono::DateTime::parse_from_rfc3339(&rate_limit.reset_at).ok()?;
+    let reset_in = (reset_at.timestamp() - Utc::now().timestamp()).max(0) as u64;
+    Some(Duration::from_secs(reset_in))
+}
+
+async fn fetch_commit_prs_rest(
+    client: &reqwest::Client,
+    url: &str,
+    sha: &str,
+) -> MietteResult<Option<PrInfo>> {
+    tracing::debug!("Fetching PRs for commit: {sha}");
+    let request = || {
+        client
+            .get(url)
+            .header("Accept", "application/vnd.github+json")
+    };
+    let Some(response) = send_with_retry(ForgeKind::GitHub, request).await? else {
+        tracing::info!("No PRs found for commit {sha}");
+        return Ok(None);
+    };
+    let mut pulls: Vec<RestPullRequest> = response.json().await.into_diagnostic()?;
+
+    // First try to find a closed PR, if no closed PR exists, use the first one
+    let pr = pulls
+        .iter()
+        .position(|pr| pr.state == "closed")
+        .or((!pulls.is_empty()).then_some(0))
+        .map(|idx| PrInfo::from(pulls.swap_remove(idx)));
+    match &pr {
+        Some(pr) => tracing::info!("Found PR #{} for commit {sha}", pr.number),
+        None => tracing::info!("No PRs found for commit {sha}"),
+    }
+    Ok(pr)
+}
+
+pub async fn create_release(
+    forge: &ForgeRepo,
+    token: &str,
+    options: &ReleaseOptions<'_>,
+) -> MietteResult<Release> {
+    let octocrab = client(token, &forge.api_url)?;
+    tracing::info!(
+        "Creating GitHub release {} (draft: {}, prerelease: {})",
+        options.tag_name,
+        options.draft,
+        options.prerelease
+    );
+
+    octocrab
+        .repos(&forge.owner, &forge.name)
+        .releases()
+        .create(options.tag_name)
+        .name(options.tag_name)
+        .body(options.body)
+        .draft(options.draft)
+        .prerelease(options.prerelease)
+        .send()
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create GitHub release {}", options.tag_name))
+}
+
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Upload the assets and a generated `SHA256SUMS` to the release.
+/// Existing assets with the same name are replaced.
+pub async fn upload_assets(
+    forge: &ForgeRepo,
+    token: &str,
+    release: &Release,
+    assets: &[ReleaseAsset],
+) -> MietteResult<Vec<Asset>> {
+    let octocrab = client(token, &forge.api_url)?;
+    let uploads_client = client(token, origin(&release.upload_url))?;
+    let checksums = assets::sha256sums(assets)?;
+
+    let mut uploads: Vec<(&str, &str, Vec<u8>)> = Vec::with_capacity(assets.len() + 1);
+    for asset in assets {
+        let data = std::fs::read(&asset.path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read asset {}", asset.path.display()))?;
+        uploads.push((asset.name.as_str(), asset.content_type.as_str(), data));
+    }
+    uploads.push((CHECKSUMS_NAME, "text/plain", checksums.into_bytes()));
+
+    let mut uploaded = Vec::with_capacity(uploads.len());
+    for (name, content_type, data) in uploads {
+        let mut attempt = 1;
+        let asset = loop {
+            match upload_asset(
+                &octocrab,
+                &uploads_client,
+                forge,
+                release,
+                name,
+                content_type,
+                &data,
+            )
+            .await
+            {
+                Ok(asset) => break asset,
+                Err(e) if attempt < UPLOAD_ATTEMPTS && is_retryable(&e) => {
+                    let delay = std::time::Duration::from_secs(2u64.pow(attempt));
+                    tracing::warn!(
+                        "Uploading {name} failed (attempt {attempt}/{UPLOAD_ATTEMPTS}): {e}. Retrying in {delay:?}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Failed to upload asset {name}"));
+                }
+            }
+        };
+        tracing::info!("Uploaded {} ({content_type})", asset.name);
+        uploaded.push(asset);
+    }
+    Ok(uploaded)
+}
+
+async fn upload_asset(
+    octocrab: &Octocrab,
+    uploads_client: &Octocrab,
+    forge: &ForgeRepo,
+    release: &Release,
+    name: &str,
+    content_type: &str,
+    data: &[u8],
+) -> octocrab::Result<Asset> {
+    // Replace an existing asset, e.g. left behind by a failed upload
+    let existing = octocrab
+        .repos(&forge.owner, &forge.name)
+        .releases()
+        .assets(release.id.0)
+        .per_page(100)
+        .send()
+        .await?;
+    if let Some(existing) = existing.items.iter().find(|a| a.name == name) {
+        tracing::info!("Deleting existing asset {name}");
+        octocrab
+            .repos(&forge.owner, &forge.name)
+            .release_assets()
+            .delete(existing.id.0)
+            .await?;
+    }
+
+    // Octocrab's upload_asset always sends application/octet-stream
+    let upload_url = release
+        .upload_url
+        .split('{')
+        .next()
+        .unwrap_or(&release.upload_url);
+    let request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(format!("{upload_url}?name={}", encode_component(name)))
+        .header(http::header::CONTENT_TYPE, content_type)
+        .header(http::header::CONTENT_LENGTH, data.len())
+        .body(data.to_vec())
+        .expect("Upload request is always valid");
+
+    let response = uploads_client.execute(request).await?;
+    let response = octocrab::map_github_error(response).await?;
+    Asset::from_response(response).await
+}
+
+/// Client errors (4xx) will fail again, anything else may be transient.
+fn is_retryable(error: &octocrab::Error) -> bool {
+    match error {
+        octocrab::Error::GitHub { source, .. } => !source.status_code.is_client_error(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::Request;
+    use wiremock::Respond;
+    use wiremock::ResponseTemplate;
+    use wiremock::matchers::body_bytes;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::matchers::header;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+    use wiremock::matchers::query_param;
+
+    fn forge(server: &MockServer) -> ForgeRepo {
+        ForgeRepo {
+            kind: ForgeKind::GitHub,
+            authority: "github.com".to_string(),
+            owner: "owner".to_string(),
+            name: "repo".to_string(),
+            api_url: server.uri(),
+        }
+    }
+
+    fn release_json(server: &MockServer, tag_name: &str) -> serde_json::Value {
+        let uri = server.uri();
+        json!({
+            "url": format!("{uri}/repos/owner/repo/releases/1"),
+            "html_url": format!("https://github.com/owner/repo/releases/tag/{tag_name}"),
+            "assets_url": format!("{uri}/repos/owner/repo/releases/1/assets"),
+            "upload_url": format!("{uri}/uploads/repos/owner/repo/releases/1/assets{{?name,label}}"),
+            "tarball_url": null,
+            "zipball_url": null,
+            "id": 1,
+            "node_id": "RE_1",
+            "tag_name": tag_name,
+            "target_commitish": "main",
+            "name": tag_name,
+            "body": "",
+            "draft": false,
+            "prerelease": false,
+            "created_at": null,
+            "published_at": null,
+            "author": null,
+            "assets": [],
+        })
+    }
+
+    fn asset_json(server: &MockServer, id: u64, name: &str) -> serde_json::Value {
+        let uri = server.uri();
+        json!({
+            "url": format!("{uri}/repos/owner/repo/releases/assets/{id}"),
+            "browser_download_url": format!("https://github.com/owner/repo/releases/download/v1.2.0/{name}"),
+            "id": id,
+            "node_id": format!("RA_{id}"),
+            "name": name,
+            "label": null,
+            "state": "uploaded",
+            "content_type": "text/plain",
+            "size": 5,
+            "download_count": 0,
+            "created_at": "2026-01-01T00:00:00Z",
+            "updated_at": "2026-01-01T00:00:00Z",
+            "uploader": null,
+        })
+    }
+
+    fn release(server: &MockServer) -> Release {
+        serde_json::from_value(release_json(server, "v1.2.0")).unwrap()
+    }
+
+    /// Write an asset file unique to the test and return it.
+    fn asset(test: &str, contents: &str) -> ReleaseAsset {
+        let path = std::env::temp_dir().join(format!("tagge-{test}-{}.txt", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        ReleaseAsset {
+            name: "notes.txt".to_string(),
+            path,
+            content_type: "text/plain".to_string(),
+        }
+    }
+
+    async fn mock_uploads(server: &MockServer, contents: &str) {
+        Mock::given(method("POST"))
+            .and(path("/uploads/repos/owner/repo/releases/1/assets"))
+            .and(query_param("name", "notes.txt"))
+            .and(header("content-type", "text/plain"))
+            .and(body_bytes(contents.as_bytes()))
+            .respond_with(ResponseTemplate::new(201).set_body_json(asset_json(
+                server,
+                11,
+                "notes.txt",
+            )))
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/uploads/repos/owner/repo/releases/1/assets"))
+            .and(query_param("name", CHECKSUMS_NAME))
+            .respond_with(ResponseTemplate::new(201).set_body_json(asset_json(
+                server,
+                12,
+                CHECKSUMS_NAME,
+            )))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn create_release_payload() {
+        // (tag, --draft, --prerelease, expected draft, expected prerelease)
+        let cases = [
+            ("v1.2.0", false, false, false, false),
+            ("v1.2.0", true, false, true, false),
+            ("v1.2.0", false, true, false, true),
+            ("v1.2.0-rc.1", false, false, false, true),
+            ("1.2.0-beta.3", true, false, true, true),
+        ];
+        for (tag_name, draft, prerelease, expected_draft, expected_prerelease) in cases {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .and(path("/repos/owner/repo/releases"))
+                .and(body_partial_json(json!({
+                    "tag_name": tag_name,
+                    "name": tag_name,
+                    "body": "- Notes",
+                    "draft": expected_draft,
+                    "prerelease": expected_prerelease,
+                })))
+                .respond_with(
+                    ResponseTemplate::new(201).set_body_json(release_json(&server, tag_name)),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let options = ReleaseOptions::new(tag_name, "- Notes", draft, prerelease);
+            let release = create_release(&forge(&server), "token", &options)
+                .await
+                .unwrap_or_else(|e| panic!("{tag_name}: {e:?}"));
+            assert_eq!(release.tag_name, tag_name);
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_assets_with_checksums() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/1/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(0)
+            .mount(&server)
+            .await;
+        mock_uploads(&server, "hello").await;
+
+        let release = release(&server);
+        let assets = [asset("upload", "hello")];
+        let uploaded = upload_assets(&forge(&server), "token", &release, &assets)
+            .await
+            .unwrap();
+        std::fs::remove_file(&assets[0].path).unwrap();
+
+        let names: Vec<_> = uploaded.iter().map(|asset| asset.name.as_str()).collect();
+        assert_eq!(names, ["notes.txt", CHECKSUMS_NAME]);
+    }
+
+    #[tokio::test]
+    async fn upload_assets_replaces_existing() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/owner/repo/releases/1/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([asset_json(
+                &server,
+                7,
+                "notes.txt"
+            )])))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/repos/owner/repo/releases/assets/7"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        mock_uploads(&server, "again").await;
+
+        let release = release(&server);
+        let assets = [asset("replace", "again")];
+        let uploaded = upload_assets(&forge(&server), "token", &release, &assets)
+            .await
+            .unwrap();
+        std::fs::remove_file(&assets[0].path).unwrap();
+
+        assert_eq!(uploaded[0].id.0, 11);
+    }
+
+    /// Commit SHAs whose PR number is the commit number, starting at 1.
+    fn shas(count: u64) -> Vec<String> {
+        (1..=count).map(|i| format!("{i:040x}")).collect()
+    }
+
+    fn pr_number(sha: &str) -> u64 {
+        u64::from_str_radix(sha, 16).unwrap()
+    }
+
+    /// Answers every aliased commit of a GraphQL query with its merged PR.
+    struct GraphQlCommits {
+        remaining: u64,
+        reset_at: String,
+    }
+
+    impl Respond for GraphQlCommits {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: serde_json::Value = request.body_json().unwrap();
+            let query = body["query"].as_str().unwrap();
+            let alias = regex::Regex::new(r#"(c\d+): object\(oid: "(\w+)"\)"#).unwrap();
+            let mut repository = serde_json::Map::new();
+            for captures in alias.captures_iter(query) {
+                let number = pr_number(&captures[2]);
+                repository.insert(
+                    captures[1].to_string(),
+                    json!({ "associatedPullRequests": { "nodes": [{
+                        "number": number,
+                        "state": "MERGED",
+                        "title": format!("PR {number}"),
+                        "author": { "login": "octocat" },
+                        "labels": { "nodes": [{ "name": "bug" }] },
+                    }]}}),
+                );
+            }
+            ResponseTemplate::new(200).set_body_json(json!({
+                "data": {
+                    "rateLimit": { "cost": 1, "remaining": self.remaining, "resetAt": self.reset_at },
+                    "repository": repository,
+                }
+            }))
+        }
+    }
+
+    fn rest_pulls(number: u64) -> serde_json::Value {
+        json!([{
+            "url": format!("https://api.github.com/repos/owner/repo/pulls/{number}"),
+            "id": number,
+            "number": number,
+            "state": "closed",
+            "title": format!("PR {number}"),
+            "locked": false,
+            "maintainer_can_modify": false,
+            "user": null,
+            "labels": [],
+            "head": { "ref": "feature", "sha": "0".repeat(40) },
+            "base": { "ref": "main", "sha": "0".repeat(40) },
+        }])
+    }
+
+    /// Answer the REST lookup of `sha` with its PR, after the given rate limit responses.
+    async fn mock_rest_commit(server: &MockServer, sha: &str, rate_limited: Vec<ResponseTemplate>) {
+        let route = format!("/repos/owner/repo/commits/{sha}/pulls");
+        for response in rate_limited {
+            Mock::given(method("GET"))
+                .and(path(route.as_str()))
+                .respond_with(response)
+                .up_to_n_times(1)
+                .expect(1)
+                .mount(server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path(route.as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(rest_pulls(pr_number(sha))))
+            .mount(server)
+            .await;
+    }
+
+    fn numbers(prs: &[(String, Option<PrInfo>)]) -> Vec<Option<u64>> {
+        prs.iter()
+            .map(|(_, pr)| pr.as_ref().map(|pr| pr.number))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_in_graphql_batches() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .respond_with(GraphQlCommits {
+                remaining: 5000,
+                reset_at: Utc::now().to_rfc3339(),
+            })
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let commits = shas(120);
+        let prs = fetch_prs(&forge(&server), "token", commits.clone().into_iter())
+            .await
+            .unwrap();
+
+        let expected: Vec<_> = (1..=120).map(Some).collect();
+        assert_eq!(numbers(&prs), expected);
+        let shas: Vec<_> = prs.iter().map(|(sha, _)| sha.clone()).collect();
+        assert_eq!(shas, commits);
+        let pr = prs[0].1.as_ref().unwrap();
+        assert_eq!(pr.author.as_deref(), Some("octocat"));
+        assert_eq!(pr.labels, ["bug"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_falls_back_to_rest_on_graphql_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "errors": [{ "message": "Something went wrong" }] })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let commits = shas(3);
+        for sha in &commits {
+            mock_rest_commit(&server, sha, Vec::new()).await;
+        }
+
+        let prs = fetch_prs(&forge(&server), "token", commits.into_iter())
+            .await
+            .unwrap();
+        assert_eq!(numbers(&prs), [Some(1), Some(2), Some(3)]);
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_uses_rest_when_graphql_budget_is_exhausted() {
+        // The next batch would have to wait an hour, so it is looked up through REST
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .respond_with(GraphQlCommits {
+                remaining: 0,
+                reset_at: (Utc::now() + chrono::Duration::hours(1)).to_rfc3339(),
+            })
+            .expect(1)
+            .mount(&server)
+            .await;
+        let commits = shas(55);
+        for sha in &commits[50..] {
+            mock_rest_commit(&server, sha, Vec::new()).await;
+        }
+
+        let prs = fetch_prs(&forge(&server), "token", commits.into_iter())
+            .await
+            .unwrap();
+        let expected: Vec<_> = (1..=55).map(Some).collect();
+        assert_eq!(numbers(&prs), expected);
+    }
+
+    #[test]
+    fn graphql_rate_limit_wait() {
+        let data = |remaining: u64, reset_in: i64| {
+            json!({ "rateLimit": {
+                "cost": 2,
+                "remaining": remaining,
+                "resetAt": (Utc::now() + chrono::Duration::seconds(reset_in)).to_rfc3339(),
+            }})
+        };
+        assert_eq!(rate_limit_wait(&data(2, 60)), None);
+        let wait = rate_limit_wait(&data(1, 60)).unwrap();
+        assert!((Duration::from_secs(58)..=Duration::from_secs(60)).contains(&wait));
+        // A reset in the past needs no wait
+        assert_eq!(rate_limit_wait(&data(0, -60)), Some(Duration::ZERO));
+        assert_eq!(rate_limit_wait(&json!({})), None);
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_rest_waits_for_rate_limits() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&server)
+            .await;
+        let commits = shas(3);
+        let reset = Utc::now().timestamp().to_string();
+        // Secondary rate limit with retry-after
+        mock_rest_commit(
+            &server,
+            &commits[0],
+            vec![ResponseTemplate::new(403).insert_header("retry-after", "0")],
+        )
+        .await;
+        // Primary rate limit with the reset time
+        mock_rest_commit(
+            &server,
+            &commits[1],
+            vec![
+                ResponseTemplate::new(403)
+                    .insert_header("x-ratelimit-remaining", "0")
+                    .insert_header("x-ratelimit-reset", reset.as_str()),
+            ],
+        )
+        .await;
+        // A reset too far away is not waited for, the commit has no PR
+        let far_reset = (Utc::now().timestamp() + 3600).to_string();
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/repos/owner/repo/commits/{}/pulls",
+                commits[2]
+            )))
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("x-ratelimit-reset", far_reset.as_str()),
+            )
+            .mount(&server)
+            .await;
+
+        let prs = fetch_prs(&forge(&server), "token", commits.into_iter())
+            .await
+            .unwrap();
+        assert_eq!(numbers(&prs), [Some(1), Some(2), None]);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::forge::ForgeKind;
+use crate::forge::ForgeRepo;
+use crate::forge::PrInfo;
+use crate::forge::Release;
+use crate::forge::ReleaseOptions;
+use crate::forge::encode_component;
+use crate::forge::fetch_prs_per_commit;
+use crate::forge::http_client;
+use crate::forge::send_with_retry;
+use crate::output::say;
+use colored::Colorize;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use serde::Deserialize;
+
+fn client(token: &str) -> MietteResult<reqwest::Client> {
+    http_client("PRIVATE-TOKEN", token)
+}
+
+fn project_url(forge: &ForgeRepo) -> String {
+    let project = format!("{}/{}", forge.owner, forge.name);
+    format!("{}/projects/{}", forge.api_url, encode_component(&project))
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    iid: u64,
+    title: String,
+    state: String,
+    author: Option<User>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+impl From<MergeRequest> for PrInfo {
+    fn from(mr: MergeRequest) -> Self {
+        Self {
+            number: mr.iid,
+            reference: format!("!{}", mr.iid),
+            title: mr.title,
+            author: mr.author.map(|a| a.username),
+            labels: mr.labels,
+        }
+    }
+}
+
+/// Find the merge request for each commit.
+pub async fn fetch_prs(
+    forge: &ForgeRepo,
+    token: &str,
+    commit_shas: impl Iterator<Item = String>,
+) -> MietteResult<Vec<(String, Option<PrInfo>)>> {
+    let client = client(token)?;
+    let project_url = project_url(forge);
+    Ok(fetch_prs_per_commit(ForgeKind::GitLab, commit_shas, |sha| {
+        let client = &client;
+        let url = format!("{project_url}/repository/commits/{sha}/merge_requests");
+        async move { fetch_commit_mr(client, &url, &sha).await }
+    })
+    .await)
+}
+
+async fn fetch_commit_mr(
+    client: &reqwest::Client,
+    url: &str,
+    sha: &str,
+) -> MietteResult<Option<PrInfo>> {
+    tracing::debug!("Fetching MRs for commit: {sha}");
+    // Commits unknown to GitLab have no MRs
+    let Some(response) = send_with_retry(ForgeKind::GitLab, || client.get(url)).await? else {
+        tracing::info!("Commit {sha} not found on GitLab");
+        return Ok(None);
+    };
+    let mut mrs: Vec<MergeRequest> = response.json().await.into_diagnostic()?;
+
+    // Prefer merged, then closed, then open MRs
+    let pr = ["merged", "closed", "opened"]
+        .iter()
+        .find_map(|state| mrs.iter().position(|mr| mr.state == *state))
+        .map(|idx| PrInfo::from(mrs.swap_remove(idx)));
+    match &pr {
+        Some(pr) => tracing::info!("Found MR {} for commit {sha}", pr.reference),
+        None => tracing::info!("No MRs found for commit {sha}"),
+    }
+    Ok(pr)
+}
+
+#[derive(Deserialize)]
+struct CreatedRelease {
+    #[serde(rename = "_links")]
+    links: Option<ReleaseLinks>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseLinks {
+    #[serde(rename = "self")]
+    self_url: Option<String>,
+}
+
+pub async fn create_release(
+    forge: &ForgeRepo,
+    token: &str,
+    options: &ReleaseOptions<'_>,
+) -> MietteResult<Release> {
+    let client = client(token)?;
+    tracing::info!("Creating GitLab release {}", options.tag_name);
+    if options.draft {
+        say!(
+            "{}",
+            "Warning: GitLab has no draft releases, publishing the release!".yellow()
+        );
+    }
+    if options.prerelease {
+        tracing::info!("GitLab has no pre-release flag, ignoring it");
+    }
+
+    let url = format!("{}/releases", project_url(forge));
+    let body = serde_json::json!({
+        "tag_name": options.tag_name,
+        "name": options.tag_name,
+        "description": options.body,
+    });
+    let response = send_with_retry(ForgeKind::GitLab, || client.post(&url).json(&body))
+        .await
+        .and_then(|response| {
+            response
+                .ok_or_else(|| miette!("GitLab project {}/{} not found", forge.owner, forge.name))
+        })
+        .wrap_err_with(|| format!("Failed to create GitLab release {}", options.tag_name))?;
+    let created: CreatedRelease = response.json().await.into_diagnostic()?;
+
+    let url = created
+        .links
+        .and_then(|l| l.self_url)
+        .unwrap_or_else(|| forge.release_url(options.tag_name));
+    Ok(Release::GitLab { url })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+    use wiremock::matchers::body_json;
+    use wiremock::matchers::header;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+
+    /// Project `group/sub/repo`, which GitLab addresses as `group%2Fsub%2Frepo`.
+    fn forge(server: &MockServer) -> ForgeRepo {
+        ForgeRepo {
+            kind: ForgeKind::GitLab,
+            authority: "gitlab.example.com".to_string(),
+            owner: "group/sub".to_string(),
+            name: "repo".to_string(),
+            api_url: server.uri(),
+        }
+    }
+
+    fn mr(iid: u64, state: &str) -> serde_json::Value {
+        json!({
+            "iid": iid,
+            "title": format!("MR {iid}"),
+            "state": state,
+            "author": { "username": "dev" },
+            "labels": ["feature"],
+        })
+    }
+
+    async fn mock_commit_mrs(server: &MockServer, sha: &str, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/projects/group%2Fsub%2Frepo/repository/commits/{sha}/merge_requests"
+            )))
+            .and(header("PRIVATE-TOKEN", "token"))
+            .respond_with(response)
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_prefers_merged() {
+        let server = MockServer::start().await;
+        let merged = json!([mr(1, "opened"), mr(2, "merged"), mr(3, "closed")]);
+        mock_commit_mrs(
+            &server,
+            "aaa",
+            ResponseTemplate::new(200).set_body_json(merged),
+        )
+        .await;
+        let closed = json!([mr(4, "opened"), mr(5, "closed")]);
+        mock_commit_mrs(
+            &server,
+            "bbb",
+            ResponseTemplate::new(200).set_body_json(closed),
+        )
+        .await;
+        mock_commit_mrs(
+            &server,
+            "ccc",
+            ResponseTemplate::new(200).set_body_json(json!([])),
+        )
+        .await;
+
+        let shas = ["aaa", "bbb", "ccc"].map(String::from);
+        let prs = fetch_prs(&forge(&server), "token", shas.into_iter())
+            .await
+            .unwrap();
+
+        let found: Vec<_> = prs
+            .iter()
+            .map(|(sha, pr)| (sha.as_str(), pr.as_ref().map(|pr| pr.reference.as_str())))
+            .collect();
+        assert_eq!(
+            found,
+            [("aaa", Some("!2")), ("bbb", Some("!5")), ("ccc", None)]
+        );
+        let pr = prs[0].1.as_ref().unwrap();
+        assert_eq!(pr.number, 2);
+        assert_eq!(pr.title, "MR 2");
+        assert_eq!(pr.author.as_deref(), Some("dev"));
+        assert_eq!(pr.labels, ["feature"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_prs_unknown_commit() {
+        let server = MockServer::start().await;
+        mock_commit_mrs(&server, "aaa", ResponseTemplate::new(404)).await;
+        // Failed lookups are shown without an MR instead of failing the run
+        mock_commit_mrs(&server, "bbb", ResponseTemplate::new(403)).await;
+
+        let shas = ["aaa", "bbb"].map(String::from);
+        let prs = fetch_prs(&forge(&server), "token", shas.into_iter())
+            .await
+            .unwrap();
+        assert!(prs.iter().all(|(_, pr)| pr.is_none()));
+    }
+
+    #[tokio::test]
+    async fn create_release_body() {
+        for (links, expected_url) in [
+            (
+                json!({ "self": "https://gitlab.example.com/group/sub/repo/-/releases/v1.2.0" }),
+                "https://gitlab.example.com/group/sub/repo/-/releases/v1.2.0",
+            ),
+            (
+                json!(null),
+                "https://gitlab.example.com/group/sub/repo/-/releases/v1.2.0",
+            ),
+        ] {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .and(path("/projects/group%2Fsub%2Frepo/releases"))
+                .and(header("PRIVATE-TOKEN", "token"))
+                .and(body_json(json!({
+                    "tag_name": "v1.2.0",
+                    "name": "v1.2.0",
+                    "description": "- Notes",
+                })))
+                .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "_links": links })))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let options = ReleaseOptions::new("v1.2.0", "- Notes", false, false);
+            let release = create_release(&forge(&server), "token", &options)
+                .await
+                .unwrap();
+            assert_eq!(release.url(), expected_url);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_release_missing_project() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let options = ReleaseOptions::new("v1.2.0", "", false, false);
+        let Err(error) = create_release(&forge(&server), "token", &options).await else {
+            panic!("Should fail for a missing project");
+        };
+        assert!(
+            format!("{error:?}").contains("group/sub/repo not found"),
+            "{error:?}"
+        );
+    }
+}
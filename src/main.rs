@@ -1,161 +1,512 @@
 mod args;
+mod assets;
+mod bump_labels;
+mod changelog;
+mod ci;
+mod config;
+mod conventional;
+mod credentials;
+mod exit;
+mod forge;
+mod gitea;
+mod github;
+mod gitlab;
+mod notes;
+mod output;
+mod remote_url;
+mod signing;
 mod version;
+mod version_files;
 
 use crate::version::ToVString;
+use crate::version::apply_suffix;
+use crate::version::bump_version;
+use crate::version::ensure_greater;
+use crate::version::expand_metadata;
+use crate::version::make_prerelease;
+use args::BumpArgs;
 use args::CliArgs;
+use args::ConfigAction;
+use args::RangeArgs;
+use args::VerifyArgs;
 use args::VersionBump;
-use clap::Parser;
+use assets::ReleaseAsset;
+use bump_labels::LabelBump;
+use clap::CommandFactory;
+use clap::FromArgMatches;
 use colored::Colorize;
-use futures::future::join_all;
+use config::Config;
+use config::Env;
+use config::print_settings;
+use credentials::GitAuth;
+use exit::Exit;
+use forge::ForgeRepo;
+use forge::PrInfo;
 use git2::Commit;
-use git2::Cred;
+use git2::ErrorCode;
 use git2::FetchOptions;
+use git2::ObjectType;
 use git2::Oid;
-use git2::RemoteCallbacks;
+use git2::PushOptions;
 use git2::Repository;
+use git2::Status;
 use git2::Tag;
 use miette::Context;
 use miette::IntoDiagnostic;
 use miette::Result as MietteResult;
 use miette::miette;
-use octocrab::Octocrab;
-use octocrab::models::IssueState;
-use octocrab::models::pulls::PullRequest;
+use notes::LabelGroup;
+use notes::NoteGroup;
+use output::say;
 use semver::Version;
+use signing::SignMode;
+use signing::Signer;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Write as FmtWrite;
 use std::io;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
+use std::process::ExitCode;
 use std::process::Stdio;
+use version_files::FileUpdate;
+use version_files::VersionFile;
 
 #[tokio::main]
-async fn main() -> MietteResult<()> {
-    let mut cli_args = CliArgs::parse();
+async fn main() -> ExitCode {
+    let Err(e) = run().await else {
+        return ExitCode::SUCCESS;
+    };
+    // Without the "Error: " prefix in JSON mode, so stderr is a JSON document
+    if output::is_json() {
+        eprintln!("{e:?}");
+    } else {
+        eprintln!("Error: {e:?}");
+    }
+    ExitCode::from(Exit::status_of(&e))
+}
+
+async fn run() -> MietteResult<()> {
+    let matches = CliArgs::command().get_matches();
+    let mut cli_args = CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let command = cli_args.take_command(&matches).unwrap_or_else(|e| e.exit());
+    output::init(cli_args.output);
+
+    let repo_path = cli_args.path()?;
+    let repo = repository_from_path(&repo_path, cli_args.path == ".")?;
+
+    // Fill in defaults from the environment and the config files
+    let settings = Config::load(&repo)?.apply(&mut cli_args, &matches, &Env::from_process())?;
 
     if cli_args.debug {
         tracing_subscriber::fmt::init();
         tracing::info!("Running in debug mode!");
     }
-    let repo_path = cli_args.path()?;
-    let repo = repository_from_path(&repo_path, cli_args.path == ".")?;
-    let (repo_owner, repo_name) = github_owner_and_repo(&repo)?;
 
-    tracing::info!("Repo owner: {repo_owner}, repo name: {repo_name}");
+    match command {
+        args::Command::Config {
+            action: ConfigAction::Show,
+        } => {
+            print_settings(&settings);
+            Ok(())
+        }
+        args::Command::List(list_args) => list_tags(&repo, list_args.limit),
+        args::Command::Verify(verify_args) => {
+            verify_tag(&repo, &verify_args, &cli_args.tag_args.version_files)
+        }
+        args::Command::Show(range) => Session::new(repo, repo_path, cli_args)?.show(&range).await,
+        args::Command::Changelog(range) => {
+            Session::new(repo, repo_path, cli_args)?
+                .changelog(&range)
+                .await
+        }
+        args::Command::Bump(mut bump_args) => {
+            bump_args
+                .resolve_level(&cli_args)
+                .unwrap_or_else(|e| e.exit());
+            Session::new(repo, repo_path, cli_args)?
+                .bump(&bump_args)
+                .await
+        }
+        args::Command::Init(_) => Session::new(repo, repo_path, cli_args)?.init().await,
+    }
+}
 
-    // Check gh token if PR tags are requested
-    let token = if cli_args.use_pr {
-        let Some(token) = cli_args.gh_token.take().or_else(|| get_gh_token().ok()) else {
-            println!(
-                "{}",
-                "❌ No GitHub token provided!
-Please provide a GitHub token using the --gh-token option
-or set the GH_TOKEN environment variable.
+/// The repository with its forge and credentials, for the commands that read commits.
+struct Session {
+    repo: Repository,
+    repo_path: PathBuf,
+    cli_args: CliArgs,
+    forge: Option<ForgeRepo>,
+    git_auth: GitAuth,
+    /// Forge token, checked if PRs are looked up or a release is created
+    token: Option<String>,
+}
 
-Example:
-    export GH_TOKEN=your_token_here
+/// Commits of a range with their PRs, and the release notes made of them.
+struct Changes<'a> {
+    /// SemVer tag the commits follow, if any
+    latest: Option<LatestTag<'a>>,
+    /// Start of the range, `None` when the commits start at the first commit
+    from: Option<Oid>,
+    /// End of the range, `None` for HEAD
+    to: Option<Commit<'a>>,
+    commits: Vec<Commit<'a>>,
+    prs: Option<Vec<(String, Option<PrInfo>)>>,
+    note_groups: Vec<NoteGroup>,
+}
 
-See: https://github.com/settings/tokens for more info."
-                    .red()
+impl Session {
+    fn new(repo: Repository, repo_path: PathBuf, mut cli_args: CliArgs) -> MietteResult<Self> {
+        if cli_args.pr_titles && !cli_args.use_pr {
+            say!(
+                "{}",
+                "Warning: --pr-titles has no effect without --use-pr\n".yellow()
             );
+        }
+        // A missing remote or an unknown forge only disables the forge features
+        let forge = match ForgeRepo::from_remote(&repo, cli_args.remote(), &cli_args.forge_hosts) {
+            Ok(forge) => {
+                tracing::info!(
+                    "Forge: {} at {}, repo owner: {}, repo name: {}",
+                    forge.kind,
+                    forge.api_url,
+                    forge.owner,
+                    forge.name
+                );
+                Some(forge)
+            }
+            Err(e) if cli_args.tag_args.create_release => {
+                return Err(e.wrap_err("Cannot create a release without a forge"));
+            }
+            Err(e) => {
+                if cli_args.use_pr {
+                    say!("{}", format!("Warning: PR lookup disabled: {e}\n").yellow());
+                    cli_args.use_pr = false;
+                } else {
+                    tracing::info!("No forge: {e:?}");
+                }
+                None
+            }
+        };
+
+        // Only send a token to a known forge
+        let git_auth = GitAuth {
+            token: forge.as_ref().and_then(|forge| {
+                forge
+                    .token(cli_args.token.clone())
+                    .map(|token| (forge.kind.git_username().to_string(), token))
+            }),
+            ssh_key: cli_args.ssh_key.clone(),
+        };
+
+        // Check the forge token if PR tags or a release are requested
+        let token = if let Some(forge) = &forge
+            && (cli_args.use_pr || cli_args.tag_args.create_release)
+        {
+            let Some(token) = forge.token(cli_args.token.take()) else {
+                let token_env = forge.kind.token_env();
+                return Err(miette!(
+                    code = Exit::MissingToken.code(),
+                    help = format!(
+                        "Provide a token with --token or set the {token_env} environment variable, e.g. `export {token_env}=your_token_here`"
+                    ),
+                    "No {} token provided!",
+                    forge.kind
+                ));
+            };
+            Some(token)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            repo,
+            repo_path,
+            cli_args,
+            forge,
+            git_auth,
+            token,
+        })
+    }
+
+    /// `show`: the latest tag and the commits since, or the commits of a range.
+    async fn show(&self, range: &RangeArgs) -> MietteResult<()> {
+        self.check_branch(false)?;
+        let changes = self.read_changes(range, false).await?;
+        self.report(&changes, None, None)?;
+        if output::is_json() {
+            return Ok(());
+        }
+
+        if range.from.is_some() || range.to.is_some() {
+            let from = match (&range.from, &changes.latest) {
+                (Some(from), _) => from.clone(),
+                (None, Some(latest)) => latest.name.clone(),
+                (None, None) => "the first commit".to_string(),
+            };
+            let to = range.to.as_deref().unwrap_or("HEAD");
+            print_range(&from, to, &changes.note_groups);
+        } else {
+            print_latest(changes.latest.as_ref());
+            print_changelog(&changes.note_groups);
+        }
+        Ok(())
+    }
+
+    /// `changelog`: only the release notes as Markdown, on any branch.
+    async fn changelog(&self, range: &RangeArgs) -> MietteResult<()> {
+        let changes = self.read_changes(range, false).await?;
+        self.report(&changes, None, None)?;
+        if !output::is_json() {
+            print!("{}", notes::to_markdown(&changes.note_groups));
+        }
+        Ok(())
+    }
+
+    /// `bump`: tag the next version after the latest tag.
+    async fn bump(&self, bump_args: &BumpArgs) -> MietteResult<()> {
+        let release_assets = self.release_assets()?;
+        self.check_branch(!self.cli_args.tag_args.dry_run)?;
+        let changes = self.read_changes(&RangeArgs::default(), false).await?;
+        let latest = changes
+            .latest
+            .as_ref()
+            .expect("The latest tag is required without a range");
+
+        // Unlabeled PRs are worth a look whichever bump is used
+        let label_bump = changes
+            .prs
+            .as_deref()
+            .map(|prs| bump_labels::infer_bump(prs, &self.cli_args.bump_labels));
+        if let Some(label_bump) = &label_bump
+            && !label_bump.unlabeled.is_empty()
+        {
+            say!("{}", "Warning: PRs without a bump label:".yellow());
+            for pr in &label_bump.unlabeled {
+                say!("  - {} {}", pr.reference, pr.title);
+            }
+            say!();
+        }
+
+        let new_version = match &self.cli_args.tag_args.tag {
+            Some(overridden_tag) => overridden_tag.clone(),
+            None => {
+                // Promoting a pre-release tags the same commit
+                if changes.commits.is_empty() && bump_args.level != Some(VersionBump::Release) {
+                    return Err(miette!(
+                        code = Exit::NoCommits.code(),
+                        "No commits since the latest tag {}, nothing to release!",
+                        latest.name
+                    ));
+                }
+                let bump = match bump_args.level {
+                    Some(VersionBump::Auto) => {
+                        Some(resolve_auto_bump(&changes.commits, label_bump))
+                    }
+                    bump => bump,
+                };
+                let new_version = bump_version(
+                    &latest.version,
+                    bump.as_ref(),
+                    self.cli_args.tag_args.pre.as_ref(),
+                )?;
+                // A suffix can move a pre-release below the latest version (rc.1 -> foo)
+                let new_version = decorate_version(&self.repo, new_version, &self.cli_args)?;
+                ensure_greater(&new_version, &latest.version)?;
+                new_version.to_v_string()
+            }
+        };
+        self.tag_release(&changes, &new_version, &release_assets)
+            .await
+    }
+
+    /// `init`: tag the first version, v0.1.0 unless overridden.
+    async fn init(&self) -> MietteResult<()> {
+        let release_assets = self.release_assets()?;
+        self.check_branch(!self.cli_args.tag_args.dry_run)?;
+        let changes = self.read_changes(&RangeArgs::default(), true).await?;
+
+        let new_version = match &self.cli_args.tag_args.tag {
+            Some(overridden_tag) => overridden_tag.clone(),
+            None => {
+                let mut initial_version = Version::new(0, 1, 0);
+                if let Some(pre) = &self.cli_args.tag_args.pre {
+                    initial_version.pre = make_prerelease(pre.as_str(), 1);
+                }
+                decorate_version(&self.repo, initial_version, &self.cli_args)?.to_v_string()
+            }
+        };
+        self.tag_release(&changes, &new_version, &release_assets)
+            .await
+    }
+
+    /// Resolve the asset globs of a release before anything is created.
+    fn release_assets(&self) -> MietteResult<Vec<ReleaseAsset>> {
+        let Some(forge) = &self.forge else {
+            return Ok(Vec::new());
+        };
+        if !self.cli_args.tag_args.create_release {
+            return Ok(Vec::new());
+        }
+        if !self.cli_args.tag_args.assets.is_empty() && !forge.supports_assets() {
+            return Err(miette!(
+                "Release assets are not supported on {}",
+                forge.kind
+            ));
+        }
+        assets::collect(&self.cli_args.tag_args.assets)
+    }
+
+    /// Note a branch other than the expected ones, and ask before tagging on it if `confirm`.
+    fn check_branch(&self, confirm: bool) -> MietteResult<()> {
+        let head_ref = self.repo.head().into_diagnostic()?;
+        let Some(branch_name) = head_ref.shorthand().filter(|_| head_ref.is_branch()) else {
             return Ok(());
         };
-        Some(token)
-    } else {
-        None
-    };
 
-    // Check branch
-    let head_ref = repo.head().into_diagnostic()?;
-    if head_ref.is_branch()
-        && let Some(branch_name) = head_ref.shorthand()
-    {
-        // Notify user main/master is not selected
-        if !["main", "master"]
+        // Notify user none of the expected branches is selected
+        let branches = &self.cli_args.branches;
+        if !branches
             .iter()
-            .any(|name| branch_name.contains(name))
+            .any(|name| branch_name.contains(name.as_str()))
         {
-            println!(
+            say!(
                 "{}",
-                format!("Note: You are on branch '{branch_name}', not 'main' or 'master'!\n")
-                    .yellow()
+                format!(
+                    "Note: You are on branch '{branch_name}', not one of: {}!\n",
+                    branches.join(", ")
+                )
+                .yellow()
             );
         }
-        // No need to confirm if:
-        if !cli_args.dry_run // dryrun
-                && (cli_args.bump.is_some() || cli_args.tag.is_some()) // no bump
-                && !confirm_continue("Are you sure you want to create a tag on this branch?")
+        if confirm
+            && !confirm_continue(
+                "Are you sure you want to create a tag on this branch?",
+                true,
+                Answers::from(&self.cli_args),
+            )
         {
-            return Ok(());
+            return Err(miette!(
+                code = Exit::Aborted.code(),
+                help = "Pass --yes or --no-input to create tags without being asked.",
+                "Aborted, no tag created"
+            ));
         }
+        Ok(())
     }
 
-    let mut git_fetch_task = None;
-    if !cli_args.no_fetch {
-        git_fetch_task = Some(tokio::task::spawn_blocking({
-            let repo = repository_from_path(&repo_path, cli_args.path == ".")
+    /// Start fetching the tags of the remote, unless disabled or there is no such remote.
+    fn spawn_fetch(&self) -> Option<tokio::task::JoinHandle<MietteResult<()>>> {
+        if self.cli_args.no_fetch {
+            return None;
+        }
+        if self.repo.find_remote(self.cli_args.remote()).is_err() {
+            say!(
+                "{}",
+                format!(
+                    "Note: No git remote '{}', not fetching tags!\n",
+                    self.cli_args.remote()
+                )
+                .yellow()
+            );
+            return None;
+        }
+
+        let task = tokio::task::spawn_blocking({
+            let repo = repository_from_path(&self.repo_path, self.cli_args.path == ".")
                 .expect("If we opened repo once without panic, we can do it again (hopefully)");
-            move || git_fetch(&repo)
-        }));
+            let remote = self.cli_args.remote().to_string();
+            let git_auth = self.git_auth.clone();
+            move || git_fetch(&repo, &remote, &git_auth)
+        });
         tracing::info!("Git fetch future created!");
+        Some(task)
+    }
 
+    /// Fetch the tags, resolve the range and collect its commits, their PRs and the notes.
+    /// Without a range the commits follow the latest tag, which must exist unless `init`.
+    async fn read_changes(&self, range: &RangeArgs, init: bool) -> MietteResult<Changes<'_>> {
+        let repo = &self.repo;
+        let mut git_fetch_task = self.spawn_fetch();
         // If there is no prs to be fetched await now
-        if !cli_args.use_pr {
-            git_fetch_task
-                .as_mut()
-                .unwrap()
+        if !self.cli_args.use_pr
+            && let Some(git_fetch) = git_fetch_task.take()
+        {
+            git_fetch
                 .await
                 .expect("Failed to handle blocking thread!")?;
             tracing::info!("Git fetch future awaited!");
         }
-    }
 
-    let (latest_tag, latest_version) = match (latest_tag(&repo), cli_args.init) {
-        (None, false) => {
-            println!(
-                "No tags found! Please create the first tag using --init (defaults to v0.1.0, override with --tag)!"
-            );
-            return Ok(());
+        let to = range
+            .to
+            .as_deref()
+            .map(|rev| resolve_commit(repo, rev))
+            .transpose()?;
+        let latest = match &range.from {
+            // Only a SemVer tag has a version, the notes start after any revision
+            Some(from) => find_semver_tag(repo, from),
+            None => latest_tag(repo, to.as_ref().map(Commit::id)),
+        };
+        if latest.is_none() && !init && range.from.is_none() && range.to.is_none() {
+            return Err(miette!(
+                code = Exit::NoTags.code(),
+                help =
+                    "Create the first tag using `init` (defaults to v0.1.0, override with --tag).",
+                "No tags found!"
+            ));
         }
-        (Some((t, v)), _) => (Some(t), Some(v)),
-        (None, true) => (None, None),
-    };
-
-    // Get commits between the tag and head
-    let commits = commits_between_tag_and_head(&repo, latest_tag.as_ref())?;
 
-    let prs = if let Some(token) = token
-        && cli_args.use_pr
-    {
-        let commit_hashes = commits.iter().map(|c| c.id().to_string());
+        // Get commits between the tag and head, or the requested range
+        let from = match &range.from {
+            Some(rev) => Some(resolve_commit(repo, rev)?.id()),
+            None => latest.as_ref().map(|latest| latest.tag.target_id()),
+        };
+        let commits = commits_between(repo, from, to.as_ref().map(Commit::id))?;
 
-        let fetch_prs_task = fetch_prs(&token, &repo_owner, &repo_name, commit_hashes);
-        tracing::info!("Fetch PRs future created!");
-        if let Some(git_fetch) = git_fetch_task {
-            let (prs_res, git_fetch_res) = tokio::join!(fetch_prs_task, git_fetch);
-            git_fetch_res.unwrap()?;
-            tracing::info!("Git fetch future awaited!");
-            let res = Some(prs_res?);
+        let prs = if let Some(token) = &self.token
+            && let Some(forge) = &self.forge
+            && self.cli_args.use_pr
+        {
+            let commit_hashes = commits.iter().map(|c| c.id().to_string());
+
+            let fetch_prs_task = forge.fetch_prs(token, commit_hashes);
+            tracing::info!("Fetch PRs future created!");
+            let prs = if let Some(git_fetch) = git_fetch_task {
+                let (prs_res, git_fetch_res) = tokio::join!(fetch_prs_task, git_fetch);
+                git_fetch_res.unwrap()?;
+                tracing::info!("Git fetch future awaited!");
+                prs_res?
+            } else {
+                fetch_prs_task.await?
+            };
             tracing::info!("Fetch PRs future awaited!");
-            res
+            Some(prs)
         } else {
-            let res = Some(fetch_prs_task.await?);
-            tracing::info!("Fetch PRs future awaited!");
-            res
-        }
-    } else {
-        None
-    };
+            None
+        };
+
+        let mut changes = Changes {
+            latest,
+            from,
+            to,
+            commits,
+            prs,
+            note_groups: Vec::new(),
+        };
+        changes.note_groups = self.note_groups(&changes);
+        Ok(changes)
+    }
 
-    // Make nice messages "<SHA:7> <commit summary>"
-    let commit_msgs = commits.iter().map(|c| {
+    /// Make nice messages "<SHA:7> <commit summary> (<PR>)", with the SHA and PR if requested.
+    fn commit_msg(&self, changes: &Changes, c: &Commit, text: &str) -> String {
         let mut msg = String::new();
-        let summary = c.summary().unwrap_or_default();
 
         // Write SHA if requested
-        if cli_args.use_sha {
+        if self.cli_args.use_sha {
             write!(
                 msg,
                 "{} ",
@@ -164,223 +515,298 @@ See: https://github.com/settings/tokens for more info."
             .expect("Should never fail!");
         }
 
-        write!(msg, "{summary}").expect("Should never fail");
+        write!(msg, "{text}").expect("Should never fail");
 
-        if let Some(prs) = &prs {
+        if let Some(prs) = &changes.prs {
             // Find the PR number for this commit
             let commit_id = c.id().to_string();
             let found_pr = prs
                 .iter()
                 .find(|(sha, _)| *sha == commit_id)
-                .and_then(|(_, pr_opt)| *pr_opt);
+                .and_then(|(_, pr_opt)| pr_opt.as_ref());
 
             match found_pr {
-                Some(pr_num) => write!(msg, " (#{pr_num})").expect("Should never fail"),
+                Some(pr) => write!(msg, " ({})", pr.reference).expect("Should never fail"),
                 None => write!(msg, " (N/A)").expect("Should not fail"),
             }
         }
         msg
-    });
+    }
 
-    // If we want to bump
-    let (new_tag, new_version) = if let Some(overridden_tag) = cli_args.tag {
-        if !cli_args.dry_run {
-            let new_tag = create_tag(
-                &repo,
-                &overridden_tag,
-                &generate_changelog(commit_msgs.clone()),
-            )?;
-            (Some(new_tag), Some(overridden_tag))
-        } else {
-            (None, Some(overridden_tag))
+    fn entry_msg(&self, changes: &Changes, c: &Commit, pr: Option<&PrInfo>, text: &str) -> String {
+        match pr {
+            Some(pr) => notes::pr_entry(pr, text),
+            None => self.commit_msg(changes, c, text),
         }
-    } else if let Some(bump) = cli_args.bump
-        && let Some(latest_version) = &latest_version
-    {
-        let mut new_version = bump_version(latest_version, &bump).to_v_string();
+    }
 
-        // Add optional extra suffix
-        if let Some(suffix) = cli_args.suffix {
-            write!(new_version, "-{suffix}").expect("Writing to a mutable string should not fail!");
+    /// One entry per PR when using PR titles, otherwise one per commit.
+    fn entries<'c, 'r>(
+        &self,
+        changes: &'c Changes<'r>,
+    ) -> Vec<(&'c Commit<'r>, Option<&'c PrInfo>)> {
+        match &changes.prs {
+            Some(prs) if self.cli_args.pr_titles => notes::collapse_prs(&changes.commits, prs),
+            _ => changes.commits.iter().map(|c| (c, None)).collect(),
         }
+    }
 
-        let new_tag = if !cli_args.dry_run {
-            Some(create_tag(
-                &repo,
-                &new_version,
-                &generate_changelog(commit_msgs.clone()),
-            )?)
-        } else {
-            None
-        };
-        (new_tag, Some(new_version))
-    } else if cli_args.init {
-        // Default to v0.1.0 for initial tag
-        let mut initial_version = Version::new(0, 1, 0).to_v_string();
-
-        // Add optional extra suffix
-        if let Some(suffix) = cli_args.suffix {
-            write!(initial_version, "-{suffix}")
-                .expect("Writing to a mutable string should not fail!");
-        }
-
-        let new_tag = if !cli_args.dry_run {
-            Some(create_tag(
-                &repo,
-                &initial_version,
-                &generate_changelog(commit_msgs.clone()),
-            )?)
+    fn note_groups(&self, changes: &Changes) -> Vec<NoteGroup> {
+        if changes.prs.is_some() && self.cli_args.pr_titles {
+            notes::group_by_label(self.entries(changes).into_iter().map(|(c, pr)| {
+                let group = pr.map_or(LabelGroup::Other, |pr| LabelGroup::of(&pr.labels));
+                let text = pr.map_or(c.summary().unwrap_or_default(), |pr| pr.title.as_str());
+                (group, self.entry_msg(changes, c, pr, text))
+            }))
         } else {
-            None
+            vec![NoteGroup {
+                title: None,
+                entries: changes
+                    .commits
+                    .iter()
+                    .map(|c| self.commit_msg(changes, c, c.summary().unwrap_or_default()))
+                    .collect(),
+            }]
+        }
+    }
+
+    /// Create the tag of `new_version` after the release commit, push it and create the release.
+    /// Steps after a failure see `released=false` in the CI outputs instead of no outputs.
+    async fn tag_release(
+        &self,
+        changes: &Changes<'_>,
+        new_version: &str,
+        release_assets: &[ReleaseAsset],
+    ) -> MietteResult<()> {
+        let sign_mode = self
+            .cli_args
+            .tag_args
+            .sign
+            .unwrap_or_else(|| SignMode::from_git_config(&self.repo));
+        let new_tag = match self
+            .publish(changes, new_version, release_assets, sign_mode)
+            .await
+        {
+            Ok(new_tag) => new_tag,
+            Err(e) => {
+                if let Err(output_error) = self.write_ci_outputs(changes, Some(new_version), false)
+                {
+                    tracing::warn!("Failed to write the CI outputs: {output_error:?}");
+                }
+                return Err(e);
+            }
         };
-        (new_tag, Some(initial_version))
-    } else {
-        (None, None)
-    };
 
-    print_info(
-        latest_tag.as_ref(),
-        latest_version.map(|lv| lv.to_v_string()).as_deref(),
-        new_tag.as_ref(),
-        new_version.as_deref(),
-        commit_msgs,
-    );
+        self.report(changes, Some(new_version), new_tag.as_ref())?;
+        if !output::is_json() {
+            print_info(
+                changes.latest.as_ref(),
+                new_tag.as_ref(),
+                new_version,
+                &changes.note_groups,
+                sign_mode,
+            );
+        }
+        Ok(())
+    }
 
-    Ok(())
-}
+    /// Create the release commit and the tag, push it and create the release.
+    /// Returns the tag, `None` on a dry run.
+    async fn publish(
+        &self,
+        changes: &Changes<'_>,
+        new_version: &str,
+        release_assets: &[ReleaseAsset],
+        sign_mode: SignMode,
+    ) -> MietteResult<Option<Tag<'_>>> {
+        let (repo, cli_args) = (&self.repo, &self.cli_args);
+        // Fail before the release commit
+        ensure_tag_absent(repo, new_version)?;
+
+        // Fail before the release commit if the tag cannot be signed
+        let signer = if cli_args.tag_args.dry_run {
+            None
+        } else {
+            Some(Signer::new(repo, sign_mode)?)
+        };
+        let mut updates = Vec::new();
+        if let Some(changelog_path) = &cli_args.tag_args.changelog {
+            let entries: Vec<_> = self
+                .entries(changes)
+                .into_iter()
+                .map(|(c, pr)| {
+                    let message = pr.map_or(c.message().unwrap_or_default(), |pr| &pr.title);
+                    let labels = pr.map_or(LabelGroup::Other, |pr| LabelGroup::of(&pr.labels));
+                    let (section, text) = changelog::classify(message, labels);
+                    (section, self.entry_msg(changes, c, pr, &text))
+                })
+                .collect();
+
+            let workdir = repo
+                .workdir()
+                .ok_or_else(|| miette!("Cannot write a changelog in a bare repository!"))?;
+            updates.push(changelog_update(
+                workdir,
+                Path::new(changelog_path),
+                new_version,
+                changes.latest.as_ref().map(|latest| latest.name.as_str()),
+                &entries,
+                self.forge.as_ref(),
+            )?);
+        }
+        if !updates.is_empty() || !cli_args.tag_args.version_files.is_empty() {
+            commit_release(
+                repo,
+                updates,
+                &cli_args.tag_args.version_files,
+                new_version,
+                cli_args.tag_args.dry_run,
+            )?;
+        }
 
-fn github_owner_and_repo(repo: &Repository) -> MietteResult<(String, String)> {
-    let binding = repo.find_remote("origin").into_diagnostic()?;
-    let url = binding
-        .url()
-        .ok_or_else(|| miette!("No url!"))?
-        .trim_end_matches(".git");
+        let new_tag = match &signer {
+            Some(signer) => Some(create_tag(
+                repo,
+                new_version,
+                &generate_changelog(&changes.note_groups),
+                signer,
+            )?),
+            None => None,
+        };
 
-    url.strip_prefix("git@github.com:")
-        .or_else(|| url.strip_prefix("https://github.com/"))
-        .and_then(|s| s.split_once('/'))
-        .map(|(owner, repo)| (owner.to_string(), repo.to_string()))
-        .ok_or_else(|| miette!("Failed to get repo owner and name"))
-}
+        // A release needs the tag on the remote
+        if cli_args.tag_args.push || cli_args.tag_args.create_release {
+            if new_tag.is_some() {
+                if let Err(e) = push_tag(repo, cli_args.remote(), new_version, &self.git_auth) {
+                    offer_tag_deletion(repo, new_version, &e, Answers::from(cli_args));
+                    return Err(miette!(
+                        "Failed to push tag {new_version} to {}",
+                        cli_args.remote()
+                    ));
+                }
+                say!("Pushed tag {new_version} to {}\n", cli_args.remote());
+            } else {
+                say!("Would push tag {new_version} to {}", cli_args.remote());
+            }
+        }
 
-fn get_gh_token() -> MietteResult<String> {
-    std::env::var("GH_TOKEN").into_diagnostic()
-}
+        if cli_args.tag_args.create_release
+            && let Some(forge) = &self.forge
+        {
+            let token = self
+                .token
+                .as_deref()
+                .expect("Token is checked when a release is requested");
+            let body = notes::to_markdown(&changes.note_groups);
+            let options = forge::ReleaseOptions::new(
+                new_version,
+                &body,
+                cli_args.tag_args.draft,
+                cli_args.tag_args.prerelease,
+            );
 
-async fn fetch_prs(
-    token: &str,
-    owner: &str,
-    repo_name: &str,
-    commit_shas: impl Iterator<Item = String>,
-) -> MietteResult<Vec<(String, Option<u64>)>> {
-    let octocrab = Octocrab::builder()
-        .personal_token(token)
-        .build()
-        .into_diagnostic()?;
+            if new_tag.is_some() {
+                let release = forge.create_release(token, &options).await?;
+                say!("Created {} release: {}", forge.kind, release.url());
 
-    tracing::info!("Starting PR fetching for commits");
-
-    // Prepare all requests as futures
-    let fetches = commit_shas.into_iter().map(|sha| {
-        let octocrab = octocrab.clone();
-        let owner = owner.to_string();
-        let repo_name = repo_name.to_string();
-        async move {
-            tracing::debug!("Fetching PRs for commit: {}", sha);
-            match octocrab
-                .get::<Vec<PullRequest>, _, _>(
-                    format!("/repos/{owner}/{repo_name}/commits/{sha}/pulls"),
-                    None::<&()>,
-                )
-                .await
-            {
-                Ok(pulls) => {
-                    if pulls.is_empty() {
-                        tracing::info!("No PRs found for commit {}", sha);
-                        (sha, None)
-                    } else {
-                        // First try to find a closed PR
-                        if let Some(closed_pr) =
-                            pulls.iter().find(|pr| pr.state == Some(IssueState::Closed))
-                        {
-                            tracing::info!(
-                                "Found closed PR #{} for commit {}",
-                                closed_pr.number,
-                                sha
-                            );
-                            (sha, Some(closed_pr.number))
-                        } else {
-                            // If no closed PR exists, use the first one
-                            let pr = &pulls[0];
-                            tracing::info!(
-                                "Using open PR #{} for commit {} (no closed PRs found)",
-                                pr.number,
-                                sha
-                            );
-                            (sha, Some(pr.number))
-                        }
+                if !release_assets.is_empty() {
+                    let uploaded = forge.upload_assets(token, &release, release_assets).await?;
+                    say!("Uploaded assets:");
+                    for (name, url) in uploaded {
+                        say!("  - {name} ({url})");
                     }
                 }
-                Err(e) => {
-                    tracing::error!("Failed to fetch PRs for commit {}: {}", sha, e);
-                    (sha, None)
+                say!();
+            } else {
+                say!(
+                    "Would create a {} release (draft: {}, prerelease: {})",
+                    forge.kind,
+                    options.draft,
+                    options.prerelease
+                );
+                if !release_assets.is_empty() {
+                    say!("Would upload assets:");
+                    for asset in release_assets {
+                        say!("  - {} ({})", asset.path.display(), asset.content_type);
+                    }
+                    say!("  - {}", assets::CHECKSUMS_NAME);
                 }
+                say!();
             }
         }
-    });
-
-    tracing::info!("Executing PR fetch requests in parallel");
 
-    // Run all fetches concurrently
-    let results = join_all(fetches).await;
+        Ok(new_tag)
+    }
 
-    // Flatten all PR numbers into a single Vec
-    let pr_numbers: Vec<(String, Option<u64>)> = results.into_iter().collect();
-    tracing::info!("Found {} PR associations", pr_numbers.len());
+    /// Write the CI outputs, and the report of the run in JSON mode.
+    fn report(
+        &self,
+        changes: &Changes,
+        new_version: Option<&str>,
+        new_tag: Option<&Tag>,
+    ) -> MietteResult<()> {
+        self.write_ci_outputs(changes, new_version, new_tag.is_some())?;
+
+        let latest = changes.latest.as_ref();
+        if output::is_json() {
+            let changelog = notes::to_markdown(&changes.note_groups);
+            output::print_json(&output::Report {
+                latest_tag: latest.map(|latest| latest.name.clone()),
+                latest_version: latest.map(|latest| latest.version.to_string()),
+                latest_sha: latest.map(|latest| latest.tag.target_id().to_string()),
+                from: changes.from.map(|oid| oid.to_string()),
+                to: match &changes.to {
+                    Some(to) => to.id().to_string(),
+                    None => head_sha(&self.repo)?.to_string(),
+                },
+                new_version: new_version.map(str::to_string),
+                new_tag_sha: new_tag.map(|t| t.id().to_string()),
+                dry_run: self.cli_args.tag_args.dry_run,
+                commits: changes
+                    .commits
+                    .iter()
+                    .map(|c| commit_info(c, changes.prs.as_deref()))
+                    .collect(),
+                changelog,
+            });
+        }
+        Ok(())
+    }
 
-    Ok(pr_numbers)
+    fn write_ci_outputs(
+        &self,
+        changes: &Changes,
+        new_version: Option<&str>,
+        released: bool,
+    ) -> MietteResult<()> {
+        ci::write_outputs(
+            &ci::Outputs {
+                new_version: new_version.map(|v| v.trim_start_matches('v').to_string()),
+                previous_version: changes
+                    .latest
+                    .as_ref()
+                    .map(|latest| latest.version.to_string()),
+                tag: new_version,
+                changelog: &notes::to_markdown(&changes.note_groups),
+                released,
+            },
+            self.cli_args.dotenv.as_deref(),
+        )
+    }
 }
 
-fn make_ssh_callbacks<'a>() -> MietteResult<RemoteCallbacks<'a>> {
-    let mut callbacks = RemoteCallbacks::new();
-    let attempt_count = std::cell::RefCell::new(0);
-
-    callbacks.credentials({
-        let attempt_count = attempt_count.clone();
-        move |url, username_from_url, _allowed_types| {
-            let mut count = attempt_count.borrow_mut();
-            *count += 1;
-
-            if *count > 3 {
-                return Err(git2::Error::from_str(
-                    "SSH authentication failed after 3 attempts",
-                ));
-            }
-
-            let username = username_from_url.unwrap_or("git");
-            tracing::info!(
-                "SSH Callback attempt {}: url: {url}, git username: {username}",
-                *count
-            );
-
-            tracing::info!("Trying to create ssh credentials from agent.");
-            match Cred::ssh_key_from_agent(username) {
-                Ok(cred) => {
-                    tracing::info!(
-                        "SSH key successfully obtained from agent for user '{username}'"
-                    );
-                    Ok(cred)
-                }
-                Err(e) => {
-                    tracing::error!(
-                        "Failed to obtain SSH key from agent for user '{username}': {e}"
-                    );
-                    Err(e)
-                }
-            }
-        }
-    });
-    Ok(callbacks)
+fn commit_info(commit: &Commit, prs: Option<&[(String, Option<PrInfo>)]>) -> output::CommitInfo {
+    let sha = commit.id().to_string();
+    let pr = prs
+        .and_then(|prs| prs.iter().find(|(pr_sha, _)| *pr_sha == sha))
+        .and_then(|(_, pr)| pr.as_ref());
+    output::CommitInfo {
+        summary: commit.summary().unwrap_or_default().to_string(),
+        author: commit.author().name().map(str::to_string),
+        pr: pr.map(|pr| pr.number),
+        pr_author: pr.and_then(|pr| pr.author.clone()),
+        sha,
+    }
 }
 
 fn repository_from_path(path: &Path, curdir: bool) -> MietteResult<Repository> {
@@ -429,15 +855,16 @@ fn repository_from_path(path: &Path, curdir: bool) -> MietteResult<Repository> {
     }
 }
 
-fn git_fetch(repo: &Repository) -> MietteResult<()> {
-    tracing::info!("Performing git fetch to get latest tags!");
+fn git_fetch(repo: &Repository, remote: &str, auth: &GitAuth) -> MietteResult<()> {
+    tracing::info!("Performing git fetch to get latest tags from {remote}!");
     let mut origin = repo
-        .find_remote("origin")
+        .find_remote(remote)
         .into_diagnostic()
-        .wrap_err("Could not find git remote origin!")?;
+        .wrap_err_with(|| format!("Could not find git remote {remote}!"))?;
 
     // Prepare callback authentication.
-    let callbacks = make_ssh_callbacks()?;
+    let attempts = std::cell::RefCell::new(Vec::new());
+    let callbacks = auth.callbacks(repo.config().ok(), &attempts);
 
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
@@ -446,7 +873,7 @@ fn git_fetch(repo: &Repository) -> MietteResult<()> {
     match origin.fetch(
         &[
             "refs/tags/*:refs/tags/*",
-            "refs/heads/*:refs/remotes/origin/*",
+            &format!("refs/heads/*:refs/remotes/{remote}/*"),
         ],
         Some(&mut fetch_options),
         None,
@@ -455,13 +882,15 @@ fn git_fetch(repo: &Repository) -> MietteResult<()> {
             tracing::info!("Git fetch completed successfully via libgit2");
         }
         Err(e) => {
-            // Git/ssh-agent configuration probably faulty
+            // Git/credential configuration probably faulty
             tracing::warn!("libgit2 fetch failed: {e}. Falling back to command-line git fetch");
-            tracing::info!("Git / ssh-agent configuration probably faulty.");
+            log_attempts(&attempts.borrow());
             // Perform command "git fetch"
             tracing::info!("Performing command line git fetch!");
             let output = Command::new("git")
-                .args(["fetch"])
+                .arg("-C")
+                .arg(repo.workdir().unwrap_or(repo.path()))
+                .args(["fetch", remote])
                 .stdout(Stdio::piped())
                 .output()
                 .into_diagnostic()?;
@@ -471,7 +900,77 @@ fn git_fetch(repo: &Repository) -> MietteResult<()> {
                     Ok(stdout) => tracing::info!("Git fetch stdout: \n{stdout}"),
                     Err(e) => tracing::error!("Failed to parse git fetch output: {e}"),
                 }
-                return Err(miette!("Both libgit2 and command-line git fetch failed"));
+                return Err(miette!(
+                    help = credentials_help(&attempts.borrow()),
+                    "Both libgit2 and command-line git fetch failed"
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn push_tag(repo: &Repository, remote: &str, tag_name: &str, auth: &GitAuth) -> MietteResult<()> {
+    tracing::info!("Pushing tag {tag_name} to {remote}!");
+    let mut git_remote = repo
+        .find_remote(remote)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not find git remote {remote}!"))?;
+
+    // libgit2 reports a rejected ref through this callback, not as a push error
+    let rejection = std::cell::RefCell::new(None);
+    let attempts = std::cell::RefCell::new(Vec::new());
+    let mut callbacks = auth.callbacks(repo.config().ok(), &attempts);
+    callbacks.push_update_reference(|refname, status| {
+        if let Some(status) = status {
+            tracing::error!("Remote rejected {refname}: {status}");
+            *rejection.borrow_mut() = Some(status.to_string());
+        }
+        Ok(())
+    });
+    let mut push_options = PushOptions::new();
+    push_options.remote_callbacks(callbacks);
+
+    let refspec = format!("refs/tags/{tag_name}:refs/tags/{tag_name}");
+    let result = git_remote.push(&[&refspec], Some(&mut push_options));
+    drop(push_options);
+    if let Some(status) = rejection.into_inner() {
+        return Err(miette!(
+            help = "The tag probably already exists on the remote or is protected.",
+            "{remote} rejected tag {tag_name}: {status}"
+        ));
+    }
+    match result {
+        Ok(_) => {
+            tracing::info!("Git push completed successfully via libgit2");
+        }
+        Err(e) => {
+            tracing::warn!("libgit2 push failed: {e}. Falling back to command-line git push");
+            log_attempts(&attempts.borrow());
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(repo.workdir().unwrap_or(repo.path()))
+                .args(["push", remote, &refspec])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .output()
+                .into_diagnostic()?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                tracing::info!("Git push stderr: \n{stderr}");
+                if stderr.contains("[rejected]") || stderr.contains("[remote rejected]") {
+                    return Err(miette!(
+                        help = "The tag probably already exists on the remote or is protected.",
+                        "{remote} rejected tag {tag_name}:\n{}",
+                        stderr.trim_end()
+                    ));
+                }
+                return Err(miette!(
+                    help = credentials_help(&attempts.borrow()),
+                    "Both libgit2 and command-line git push of {tag_name} to {remote} failed"
+                ));
             }
         }
     }
@@ -479,11 +978,60 @@ fn git_fetch(repo: &Repository) -> MietteResult<()> {
     Ok(())
 }
 
+fn log_attempts(attempts: &[String]) {
+    if attempts.is_empty() {
+        tracing::info!("No credentials were requested by the remote");
+    } else {
+        tracing::info!("Tried credentials: {}", attempts.join(", "));
+    }
+}
+
+fn credentials_help(attempts: &[String]) -> String {
+    if attempts.is_empty() {
+        "Check the remote URL and your network connection.".to_string()
+    } else {
+        format!(
+            "Tried credentials: {}. Set up ssh-agent, a git credential helper, --ssh-key or a token.",
+            attempts.join(", ")
+        )
+    }
+}
+
+/// Ask to delete a tag that could not be pushed, so it does not linger locally.
+fn offer_tag_deletion(repo: &Repository, tag_name: &str, error: &miette::Report, answers: Answers) {
+    eprintln!("{error:?}");
+    if !confirm_continue(&format!("Delete the local tag {tag_name}?"), false, answers) {
+        say!("Kept the local tag {tag_name}\n");
+        return;
+    }
+    match repo.tag_delete(tag_name) {
+        Ok(()) => say!("Deleted local tag {tag_name}\n"),
+        Err(e) => say!(
+            "{}",
+            format!("Failed to delete local tag {tag_name}: {}", e.message()).red()
+        ),
+    }
+}
+
+/// Start of an OpenPGP, SSH or X.509 signature in a tag object.
+const SIGNATURE_HEADERS: [&str; 3] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+    "-----BEGIN SIGNED MESSAGE-----",
+];
+
 enum GitTag<'a> {
     Lightweight(Commit<'a>),
     Annotated(Tag<'a>),
 }
 
+/// A SemVer tag with its name in the repository, which may lack the `v` prefix.
+struct LatestTag<'a> {
+    tag: GitTag<'a>,
+    name: String,
+    version: Version,
+}
+
 impl<'a> GitTag<'a> {
     fn target_id(&self) -> Oid {
         match &self {
@@ -493,20 +1041,48 @@ impl<'a> GitTag<'a> {
     }
 }
 
-fn latest_tag(repo: &Repository) -> Option<(GitTag<'_>, Version)> {
-    let tag_names = repo.tag_names(None).ok()?;
-    let mut latest: Option<(Version, &str)> = None;
+/// SemVer tags, highest precedence first.
+fn semver_tags(repo: &Repository) -> Vec<(Version, String)> {
+    let Ok(tag_names) = repo.tag_names(None) else {
+        return Vec::new();
+    };
+    let mut tags: Vec<_> = tag_names
+        .iter()
+        .flatten()
+        .filter_map(|tag_name| {
+            let version = Version::parse(tag_name.trim_start_matches('v')).ok()?;
+            Some((version, tag_name.to_string()))
+        })
+        .collect();
+    // SemVer ordering puts pre-releases before their final version (1.2.0-rc.3 < 1.2.0),
+    // build metadata does not take part in precedence
+    tags.sort_by(|(a, _), (b, _)| b.cmp_precedence(a));
+    tags
+}
 
-    for tag_name in tag_names.iter().flatten() {
-        let tag_str = tag_name.trim_start_matches('v');
-        if let Ok(ver) = Version::parse(tag_str) {
-            match &latest {
-                Some((latest_ver, _)) if &ver <= latest_ver => {}
-                _ => latest = Some((ver, tag_name)),
-            }
-        }
-    }
-    let (version, tag_name) = latest?;
+/// The highest SemVer tag, or the highest one on a commit before `before`.
+fn latest_tag(repo: &Repository, before: Option<Oid>) -> Option<LatestTag<'_>> {
+    let (version, tag_name) = semver_tags(repo).into_iter().find(|(_, tag_name)| {
+        let Some(before) = before else {
+            return true;
+        };
+        repo.revparse_single(&format!("refs/tags/{tag_name}"))
+            .and_then(|object| object.peel_to_commit())
+            .and_then(|commit| repo.graph_descendant_of(before, commit.id()))
+            .unwrap_or(false)
+    })?;
+    tag(repo, tag_name, version)
+}
+
+/// The tag named `name`, if it is a SemVer tag.
+fn find_semver_tag<'a>(repo: &'a Repository, name: &str) -> Option<LatestTag<'a>> {
+    let (version, tag_name) = semver_tags(repo)
+        .into_iter()
+        .find(|(_, tag_name)| tag_name == name)?;
+    tag(repo, tag_name, version)
+}
+
+fn tag(repo: &Repository, tag_name: String, version: Version) -> Option<LatestTag<'_>> {
     tracing::info!("Found tag name: {}", tag_name);
     tracing::info!("Found version name: {}", version);
     // Find the Tag object by name
@@ -515,52 +1091,279 @@ fn latest_tag(repo: &Repository) -> Option<(GitTag<'_>, Version)> {
         .ok()?;
 
     // Try annotated tag only (git tag -a)
-    let Some(tag) = reference.peel_to_tag().ok() else {
+    let tag = match reference.peel_to_tag() {
+        Ok(tag) => {
+            tracing::info!("Peeled tag: {tag:?}");
+            GitTag::Annotated(tag)
+        }
         // Fallback: peel to commit (lightweight)
-        let commit = reference.peel_to_commit().ok()?;
-        tracing::warn!("Falling back to lightweight tag {commit:?}!");
-        return Some((GitTag::Lightweight(commit), version));
+        Err(_) => {
+            let commit = reference.peel_to_commit().ok()?;
+            tracing::warn!("Falling back to lightweight tag {commit:?}!");
+            GitTag::Lightweight(commit)
+        }
     };
+    Some(LatestTag {
+        tag,
+        name: tag_name,
+        version,
+    })
+}
+
+/// Print the SemVer tags with their commit and date, highest precedence first.
+fn list_tags(repo: &Repository, limit: Option<usize>) -> MietteResult<()> {
+    let tags = semver_tags(repo);
+    if tags.is_empty() && !output::is_json() {
+        say!("No tags found!");
+        return Ok(());
+    }
+
+    let width = tags.iter().map(|(_, name)| name.len()).max().unwrap_or(0);
+    let mut entries = Vec::new();
+    for (version, tag_name) in tags.iter().take(limit.unwrap_or(usize::MAX)) {
+        let object = repo
+            .revparse_single(&format!("refs/tags/{tag_name}"))
+            .into_diagnostic()?;
+        let commit = object.peel_to_commit().into_diagnostic()?;
+        let date = chrono::DateTime::from_timestamp(commit.time().seconds(), 0)
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        let annotated = object.kind() == Some(ObjectType::Tag);
+        if output::is_json() {
+            entries.push(output::TagEntry {
+                name: tag_name.clone(),
+                version: version.to_string(),
+                sha: commit.id().to_string(),
+                date,
+                annotated,
+            });
+            continue;
+        }
+        let kind = if annotated {
+            "annotated"
+        } else {
+            "lightweight"
+        };
+        say!(
+            "{tag_name:width$}  {}  {date}  {}",
+            &commit.id().to_string()[..7],
+            kind.dimmed()
+        );
+    }
+    if output::is_json() {
+        output::print_json(&entries);
+    }
+    Ok(())
+}
+
+/// Check a tag and print the result of every check, failing if any of them failed.
+fn verify_tag(
+    repo: &Repository,
+    verify_args: &VerifyArgs,
+    version_files: &[VersionFile],
+) -> MietteResult<()> {
+    let tag_name = match &verify_args.tag {
+        Some(tag_name) => tag_name.clone(),
+        None => semver_tags(repo)
+            .into_iter()
+            .next()
+            .map(|(_, tag_name)| tag_name)
+            .ok_or_else(|| miette!(code = Exit::NoTags.code(), "No tags found!"))?,
+    };
+    let object = repo
+        .revparse_single(&format!("refs/tags/{tag_name}"))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Tag {tag_name} not found"))?;
+    let commit = object.peel_to_commit().into_diagnostic()?;
+    say!("Verifying tag {tag_name} ({})\n", commit.id());
+
+    let mut checks = Vec::new();
+    // A check without a result was skipped
+    let mut check = |ok: Option<bool>, message: String| {
+        match ok {
+            Some(true) => say!("{} {message}", "✓".green()),
+            Some(false) => say!("{} {message}", "✗".red()),
+            None => say!("{} {message}", "-".dimmed()),
+        }
+        checks.push(output::Check { check: message, ok });
+    };
+
+    let version = Version::parse(tag_name.trim_start_matches('v'));
+    check(
+        Some(version.is_ok()),
+        "Tag name is a SemVer version".to_string(),
+    );
+
+    match object.as_tag() {
+        Some(tag) => {
+            check(Some(true), "Tag is annotated".to_string());
+            let odb = repo.odb().into_diagnostic()?;
+            let raw = odb.read(tag.id()).into_diagnostic()?;
+            let signed = SIGNATURE_HEADERS.iter().any(|header| {
+                raw.data()
+                    .windows(header.len())
+                    .any(|w| w == header.as_bytes())
+            });
+            if signed {
+                // Trust settings like gpg.ssh.allowedSignersFile are git's, so let git check
+                let output = Command::new("git")
+                    .arg("-C")
+                    .arg(repo.workdir().unwrap_or(repo.path()))
+                    .args(["verify-tag", &tag_name])
+                    .output()
+                    .into_diagnostic()?;
+                let details = String::from_utf8_lossy(&output.stderr);
+                check(
+                    Some(output.status.success()),
+                    format!("Signature: {}", details.lines().last().unwrap_or_default()),
+                );
+            } else if verify_args.signed {
+                check(Some(false), "Tag is signed".to_string());
+            } else {
+                check(None, "Tag is not signed".to_string());
+            }
+        }
+        None => check(Some(false), "Tag is annotated".to_string()),
+    }
 
-    tracing::info!("Peeled tag: {tag:?}");
-    Some((GitTag::Annotated(tag), version))
+    // The version files as they are in the tagged commit
+    if let Ok(version) = &version {
+        let tree = commit.tree().into_diagnostic()?;
+        let version = version.to_string();
+        for file in version_files {
+            let contents = tree
+                .get_path(&file.path)
+                .and_then(|entry| entry.to_object(repo))
+                .ok()
+                .and_then(|object| object.into_blob().ok())
+                .map(|blob| String::from_utf8_lossy(blob.content()).into_owned());
+            let matches = contents.is_some_and(|contents| {
+                version_files::set_version(file, &contents, &version)
+                    .is_ok_and(|updated| updated == contents)
+            });
+            check(
+                Some(matches),
+                format!("{} has version {version}", file.path.display()),
+            );
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.ok == Some(false)).count();
+    if output::is_json() {
+        output::print_json(&output::Verification {
+            tag: tag_name.clone(),
+            sha: commit.id().to_string(),
+            checks,
+            valid: failed == 0,
+        });
+    }
+    say!();
+    if failed > 0 {
+        return Err(miette!("Tag {tag_name} failed {failed} check(s)"));
+    }
+    say!("Tag {tag_name} is valid");
+    Ok(())
 }
 
-fn bump_version(latest_version: &Version, bump: &VersionBump) -> Version {
-    let mut new_version = latest_version.clone();
-    match bump {
-        VersionBump::Major => {
-            new_version.major += 1;
-            new_version.minor = 0;
-            new_version.patch = 0;
+/// Add the optional extra suffix and build metadata to a computed version.
+fn decorate_version(
+    repo: &Repository,
+    mut version: Version,
+    cli_args: &CliArgs,
+) -> MietteResult<Version> {
+    if let Some(suffix) = &cli_args.tag_args.suffix {
+        apply_suffix(&mut version, suffix)?;
+    }
+    if let Some(template) = &cli_args.tag_args.metadata {
+        // The tag points at the release commit, which does not exist yet
+        if template.contains("{sha}")
+            && (cli_args.tag_args.changelog.is_some()
+                || !cli_args.tag_args.version_files.is_empty())
+        {
+            return Err(miette!(
+                help = "Use {date} or {env:NAME} in --metadata, or tag without --changelog and --version-file.",
+                "The {{sha}} metadata placeholder cannot be used with a release commit"
+            ));
         }
-        VersionBump::Minor => {
-            new_version.minor += 1;
-            new_version.patch = 0;
+        let head_sha = repo
+            .head()
+            .ok()
+            .and_then(|h| h.target())
+            .ok_or(miette!("Failed to get HEAD!"))?
+            .to_string();
+        version.build = expand_metadata(template, &head_sha, |name| std::env::var(name).ok())?;
+    }
+    Ok(version)
+}
+
+/// The bump from the PR labels if any PR has one, else from the Conventional Commits.
+fn resolve_auto_bump(commits: &[Commit], label_bump: Option<LabelBump>) -> VersionBump {
+    // PR labels win over commit messages
+    if let Some(label_bump) = label_bump {
+        if let Some(inferred) = label_bump.inferred {
+            let bump_name = format!("{:?}", inferred.bump).to_lowercase();
+            say!(
+                "{}",
+                format!("Auto bump: {bump_name}, driven by PR labels:").cyan()
+            );
+            for reason in &inferred.reasons {
+                say!("  - {reason}");
+            }
+            say!();
+            return inferred.bump.into();
         }
-        VersionBump::Patch => {
-            new_version.patch += 1;
+        tracing::info!("No PR bump labels found, falling back to Conventional Commits");
+    }
+
+    let inferred = conventional::infer_bump(commits);
+    let bump_name = format!("{:?}", inferred.bump).to_lowercase();
+
+    if inferred.reasons.is_empty() {
+        say!(
+            "{}",
+            format!("Auto bump: {bump_name} (no feat/fix/breaking commits found)\n").cyan()
+        );
+    } else {
+        say!("{}", format!("Auto bump: {bump_name}, driven by:").cyan());
+        for reason in &inferred.reasons {
+            say!("  - {reason}");
         }
+        say!();
     }
-    new_version
+    inferred.bump.into()
 }
 
-fn commits_between_tag_and_head<'a>(
-    repo: &'a Repository,
-    tag: Option<&GitTag>,
-) -> MietteResult<Vec<Commit<'a>>> {
-    let head = repo
-        .head()
+/// Resolve a tag, branch or SHA given on the command line to its commit.
+fn resolve_commit<'a>(repo: &'a Repository, rev: &str) -> MietteResult<Commit<'a>> {
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Revision {rev} not found"))
+}
+
+fn head_sha(repo: &Repository) -> MietteResult<Oid> {
+    repo.head()
         .ok()
         .and_then(|h| h.target())
-        .ok_or(miette!("Failed to get HEAD!"))?;
+        .ok_or(miette!("Failed to get HEAD!"))
+}
+
+/// Commits reachable from `to` (default: HEAD) but not from `from`, newest first.
+fn commits_between(
+    repo: &Repository,
+    from: Option<Oid>,
+    to: Option<Oid>,
+) -> MietteResult<Vec<Commit<'_>>> {
+    let to = match to {
+        Some(to) => to,
+        None => head_sha(repo)?,
+    };
 
     let mut revwalk = repo.revwalk().into_diagnostic()?;
-    revwalk.push(head).into_diagnostic()?;
+    revwalk.push(to).into_diagnostic()?;
 
-    if let Some(tag) = tag {
-        let tag_commit = tag.target_id();
-        revwalk.hide(tag_commit).into_diagnostic()?;
+    if let Some(from) = from {
+        revwalk.hide(from).into_diagnostic()?;
     }
 
     let mut commits = Vec::new();
@@ -573,88 +1376,352 @@ fn commits_between_tag_and_head<'a>(
     Ok(commits)
 }
 
+/// Prepend the release to the changelog file.
+fn changelog_update(
+    workdir: &Path,
+    path: &Path,
+    new_version: &str,
+    previous_tag: Option<&str>,
+    entries: &[(changelog::Section, String)],
+    forge: Option<&ForgeRepo>,
+) -> MietteResult<FileUpdate> {
+    let full_path = workdir.join(path);
+
+    let version = new_version.trim_start_matches('v');
+    let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+
+    let old = if full_path.exists() {
+        std::fs::read_to_string(&full_path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", full_path.display()))?
+    } else {
+        String::new()
+    };
+    let new = changelog::update(
+        (!old.is_empty()).then_some(old.as_str()),
+        version,
+        &date,
+        entries,
+        previous_tag,
+        new_version,
+        forge,
+    );
+    Ok(FileUpdate {
+        path: path.to_path_buf(),
+        old,
+        new,
+    })
+}
+
+/// Write the changelog and version file updates and commit them, so the tag contains them.
+/// On a dry run only the diffs are printed.
+fn commit_release(
+    repo: &Repository,
+    mut updates: Vec<FileUpdate>,
+    version_files: &[VersionFile],
+    new_version: &str,
+    dry_run: bool,
+) -> MietteResult<()> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| miette!("Cannot update files in a bare repository!"))?;
+
+    if !version_files.is_empty() {
+        let version = Version::parse(new_version.trim_start_matches('v'))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Cannot write non SemVer version {new_version} to files"))?
+            .to_string();
+        for file in version_files {
+            updates.push(version_files::bump_file(workdir, file, &version)?);
+        }
+    }
+
+    if dry_run {
+        for update in &updates {
+            update.print_diff();
+        }
+        for file in version_files {
+            if let Some(refresh) = version_files::describe_lockfile_refresh(workdir, file) {
+                say!("Would refresh lockfile {refresh}");
+            }
+        }
+        return Ok(());
+    }
+
+    // Files with uncommitted changes would end up in the release commit
+    let mut targets: Vec<_> = updates.iter().map(|update| update.path.clone()).collect();
+    targets.extend(
+        version_files
+            .iter()
+            .filter_map(|file| version_files::lockfile_path(workdir, file)),
+    );
+    ensure_clean(repo, &targets)?;
+    let mut paths = Vec::new();
+    for update in updates {
+        update.write(workdir)?;
+        paths.push(update.path);
+    }
+    for file in version_files {
+        if let Some(lockfile) = version_files::refresh_lockfile(workdir, file)? {
+            paths.push(lockfile);
+        }
+    }
+
+    commit_release_files(repo, &paths, new_version)?;
+    Ok(())
+}
+
+/// Make sure no staged changes, and no work tree changes in the files the release
+/// commit writes, would end up in the release commit.
+fn ensure_clean(repo: &Repository, paths: &[PathBuf]) -> MietteResult<()> {
+    let head_tree = repo
+        .head()
+        .into_diagnostic()?
+        .peel_to_tree()
+        .into_diagnostic()?;
+    let staged = repo
+        .diff_tree_to_index(Some(&head_tree), None, None)
+        .into_diagnostic()?;
+    if staged.deltas().len() > 0 {
+        return Err(miette!(
+            help = "Commit or unstage them before releasing.",
+            "The index contains staged changes!"
+        ));
+    }
+
+    let changed = Status::WT_NEW
+        | Status::WT_MODIFIED
+        | Status::WT_DELETED
+        | Status::WT_TYPECHANGE
+        | Status::WT_RENAMED;
+    for path in paths {
+        match repo.status_file(path) {
+            Ok(status) if status.intersects(changed) => {
+                return Err(miette!(
+                    help = "Commit or stash them before releasing.",
+                    "{} has uncommitted changes!",
+                    path.display()
+                ));
+            }
+            Ok(_) => {}
+            // A file that is created by the release, like a new changelog
+            Err(e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e).into_diagnostic(),
+        }
+    }
+    Ok(())
+}
+
+/// Create a `chore(release): <version>` commit on HEAD containing the given files.
+fn commit_release_files(
+    repo: &Repository,
+    paths: &[PathBuf],
+    new_version: &str,
+) -> MietteResult<Oid> {
+    let parent = repo
+        .head()
+        .into_diagnostic()?
+        .peel_to_commit()
+        .into_diagnostic()?;
+
+    let mut index = repo.index().into_diagnostic()?;
+    for path in paths {
+        index
+            .add_path(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to stage {}", path.display()))?;
+    }
+    index.write().into_diagnostic()?;
+    let tree = repo
+        .find_tree(index.write_tree().into_diagnostic()?)
+        .into_diagnostic()?;
+
+    let signature = repo.signature().into_diagnostic().wrap_err(
+        "Failed to get the commit signature, please configure user.name and user.email",
+    )?;
+
+    let oid = repo
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            &format!("chore(release): {new_version}"),
+            &tree,
+            &[&parent],
+        )
+        .into_diagnostic()?;
+    say!("Created release commit {oid}");
+    Ok(oid)
+}
+
+fn ensure_tag_absent(repo: &Repository, tag_name: &str) -> MietteResult<()> {
+    if repo
+        .find_reference(&format!("refs/tags/{tag_name}"))
+        .is_ok()
+    {
+        return Err(miette!(
+            code = Exit::TagExists.code(),
+            "Tag {tag_name} already exists!"
+        ));
+    }
+    Ok(())
+}
+
 fn create_tag<'a>(
     repo: &'a Repository,
     new_version: &str,
     changelog: &str,
+    signer: &Signer,
 ) -> MietteResult<Tag<'a>> {
-    // Get HEAD commit
-    // let obj = repo
-    //     .head()
-    //     .into_diagnostic()?
-    //     .peel(ObjectType::Commit)
-    //     .into_diagnostic()?;
-    // let commit = obj.as_commit().ok_or(miette!("HEAD is not a commit"))?;
-
-    // Tagger signature (from git config or fallback)
-    // let tagger = repo.signature().into_diagnostic()?;
-    // let tag_name = format!("{new_version}");
-    // Create the tag
-    // let tag_oid = repo
-    //     .tag(&tag_name, &obj, &tagger, "testiii", false)
-    //     .into_diagnostic()?;
-
-    // Return the tag object
-    // let tag_obj = repo.find_tag(tag_oid).into_diagnostic()?;
-
-    // git2-rs does not support signing tags yet!
-    // https://github.com/rust-lang/git2-rs/issues/1039
-    // Temporarily use process
-    let status = Command::new("git")
-        .args([
-            "tag",
-            "-a",
-            new_version,
-            "-s",
-            "-m",
-            &format!("Release {new_version}\n\n{changelog}"),
-        ])
-        .status()
+    ensure_tag_absent(repo, new_version)?;
+    let message = format!("Release {new_version}\n\n{changelog}");
+    let head = repo
+        .head()
+        .into_diagnostic()?
+        .peel(ObjectType::Commit)
         .into_diagnostic()?;
+    let tagger = repo
+        .signature()
+        .into_diagnostic()
+        .wrap_err("Set user.name and user.email in the git config")?;
+
+    if signer.mode == SignMode::None {
+        let tag_oid = repo
+            .tag(new_version, &head, &tagger, &message, false)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create tag {new_version}"))?;
+        return repo.find_tag(tag_oid).into_diagnostic();
+    }
 
-    if !status.success() {
-        return Err(miette!("Failed to create the signed tag"));
+    // git2 cannot sign tags, so the tag object is built and signed here
+    // https://github.com/rust-lang/git2-rs/issues/1039
+    let reference = format!("refs/tags/{new_version}");
+    let mut tag_object = tag_payload(&head, new_version, &tagger, &message).into_bytes();
+    let signature = signer
+        .sign(&tag_object)
+        .wrap_err_with(|| format!("Failed to sign tag {new_version}"))?;
+    tag_object.extend_from_slice(&signature);
+
+    let tag_oid = repo
+        .odb()
+        .into_diagnostic()?
+        .write(ObjectType::Tag, &tag_object)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write tag {new_version}"))?;
+    repo.reference(&reference, tag_oid, false, &format!("tag: {new_version}"))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create tag {new_version}"))?;
+
+    repo.find_tag(tag_oid).into_diagnostic()
+}
+
+/// The tag object as `git tag` writes it, the signature is appended to it.
+fn tag_payload(
+    target: &git2::Object,
+    tag_name: &str,
+    tagger: &git2::Signature,
+    message: &str,
+) -> String {
+    let when = tagger.when();
+    let offset = when.offset_minutes().abs();
+    let mut payload = format!(
+        "object {}\ntype commit\ntag {tag_name}\ntagger {tagger} {} {}{:02}{:02}\n\n{message}",
+        target.id(),
+        when.seconds(),
+        when.sign(),
+        offset / 60,
+        offset % 60
+    );
+    if !payload.ends_with('\n') {
+        payload.push('\n');
     }
+    payload
+}
 
-    let reference = repo
-        .revparse_single(&format!("refs/tags/{new_version}"))
-        .into_diagnostic()?;
-    let tag_obj = reference.peel_to_tag().into_diagnostic()?;
+/// How questions are answered without asking.
+#[derive(Copy, Clone)]
+struct Answers {
+    /// `--yes`, agree to everything
+    yes: bool,
+    /// `--no-input`, take the safe answer of each question, like in CI
+    no_input: bool,
+}
 
-    Ok(tag_obj)
+impl From<&CliArgs> for Answers {
+    fn from(cli_args: &CliArgs) -> Self {
+        Self {
+            yes: cli_args.yes,
+            no_input: cli_args.no_input,
+        }
+    }
 }
 
-fn confirm_continue(question: &str) -> bool {
+/// Ask a yes/no question, no is the answer if stdin is closed.
+/// `safe_answer` is taken with `--no-input` and in CI jobs: yes to continue what was
+/// asked for, no to anything destructive.
+fn confirm_continue(question: &str, safe_answer: bool, answers: Answers) -> bool {
+    if answers.yes {
+        say!("{question} Yes (--yes).\n");
+        return true;
+    }
+    if answers.no_input {
+        let answer = if safe_answer { "Yes" } else { "No" };
+        say!("{question} {answer} (--no-input).\n");
+        return safe_answer;
+    }
+    // Nobody can answer in a CI job
+    if ci::is_ci() {
+        let answer = if safe_answer { "Yes" } else { "No" };
+        say!("{question} {answer}, running in CI.\n");
+        return safe_answer;
+    }
     let mut input = String::with_capacity(5);
     loop {
-        print!("{question} (y/N): ");
-        io::stdout().flush().expect("Failed to flush stdout!");
+        // Keep stdout a valid document in JSON mode
+        if output::is_json() {
+            eprint!("{question} (y/N): ");
+        } else {
+            print!("{question} (y/N): ");
+            io::stdout().flush().expect("Failed to flush stdout!");
+        }
         input.clear();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Unable to read Stdin");
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => {
+                say!("\nNo answer, stdin is closed.");
+                return false;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                say!("\nNo answer, failed to read stdin: {e}");
+                return false;
+            }
+        }
         let answer = input.trim().to_lowercase();
         if answer.is_empty() || ["n", "no"].contains(&answer.as_str()) {
-            println!("Aborted!");
             return false;
         }
         if ["y", "yes"].contains(&answer.as_str()) {
-            println!();
+            say!();
             return true;
         }
         input.clear();
     }
 }
 
-fn generate_changelog(commit_msgs: impl Iterator<Item = String>) -> String {
+fn generate_changelog(note_groups: &[NoteGroup]) -> String {
     let mut change_log = String::new();
     let mut first = true;
-    for msg in commit_msgs {
+    for group in note_groups.iter().filter(|g| !g.entries.is_empty()) {
         if first {
             write!(&mut change_log, "Changelog:").expect("Should never panic!");
             first = false;
         }
-        write!(&mut change_log, "\n - {msg}").expect("Should never panic!");
+        if let Some(title) = group.title {
+            write!(&mut change_log, "\n\n{title}:").expect("Should never panic!");
+        }
+        for msg in &group.entries {
+            write!(&mut change_log, "\n - {msg}").expect("Should never panic!");
+        }
     }
     change_log
 }
@@ -692,52 +1759,281 @@ fn generate_tag_msg(msg_type: MsgType, tag: &GitTag, version: &str) -> String {
     msg
 }
 
-fn print_changelog(commit_msgs: impl Iterator<Item = String>) {
-    let changelog = generate_changelog(commit_msgs);
+fn print_changelog(note_groups: &[NoteGroup]) {
+    let changelog = generate_changelog(note_groups);
     if !changelog.is_empty() {
-        println!("Commits in the new tag:");
-        println!("\n{changelog}",);
+        say!("Commits in the new tag:");
+        say!("\n{changelog}",);
     } else {
-        println!("No new commits since the latest tag.")
+        say!("No new commits since the latest tag.")
+    }
+}
+
+fn print_range(from: &str, to: &str, note_groups: &[NoteGroup]) {
+    let changelog = generate_changelog(note_groups);
+    if !changelog.is_empty() {
+        say!("Commits from {from} to {to}:");
+        say!("\n{changelog}");
+    } else {
+        say!("No commits from {from} to {to}.")
+    }
+}
+
+fn print_latest(latest: Option<&LatestTag>) {
+    if let Some(LatestTag {
+        tag: GitTag::Lightweight(_),
+        ..
+    }) = latest
+    {
+        say!("NOTE: Latest tag is a lightweight tag!");
+    }
+    if let Some(latest) = latest {
+        let latest_tag = generate_tag_msg(MsgType::Latest, &latest.tag, &latest.name);
+        say!("{latest_tag}");
+    } else {
+        say!("No previous tags!");
     }
 }
 
 fn print_info(
-    latest_tag: Option<&GitTag>,
-    latest_version: Option<&str>,
+    latest: Option<&LatestTag>,
     new_tag: Option<&Tag>,
-    new_version: Option<&str>,
-    commit_msgs: impl Iterator<Item = String>,
+    new_version: &str,
+    note_groups: &[NoteGroup],
+    sign_mode: SignMode,
 ) {
-    if matches!(latest_tag, Some(GitTag::Lightweight(_))) {
-        println!("NOTE: Latest tag is a lightweight tag!");
-    }
-    if let (Some(latest_tag), Some(latest_version)) = (latest_tag, latest_version) {
-        let latest_tag = generate_tag_msg(MsgType::Latest, latest_tag, latest_version);
-        println!("{latest_tag}");
+    print_latest(latest);
+
+    if let Some(new_tag) = new_tag {
+        let new_tag = generate_tag_msg(
+            MsgType::New,
+            &GitTag::Annotated(new_tag.clone()),
+            new_version,
+        );
+        say!("{new_tag}");
+        print_changelog(note_groups);
     } else {
-        println!("No previous tags!");
+        say!("New version: {new_version}\n");
+        say!(
+            "Command: \n{} -m \"Release {new_version}\n",
+            sign_mode.git_tag_command(new_version)
+        );
+        say!("{}\"", generate_changelog(note_groups));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repository with `Cargo.toml` at v0.1.0, tagged, and one commit after the tag.
+    fn released_repo(name: &str) -> (PathBuf, Repository) {
+        let dir = std::env::temp_dir().join(format!("tagge-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Tagge").unwrap();
+        config.set_str("user.email", "tagge@example.com").unwrap();
+
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("README.md"), "# a\n").unwrap();
+        let initial = commit_all(&repo, "Initial commit");
+        repo.tag_lightweight(
+            "v0.1.0",
+            repo.find_commit(initial).unwrap().as_object(),
+            false,
+        )
+        .unwrap();
+
+        std::fs::write(dir.join("notes.txt"), "notes\n").unwrap();
+        commit_all(&repo, "fix: Add notes");
+        (dir, repo)
     }
 
-    if let Some(new_version) = new_version {
-        if let Some(new_tag) = new_tag {
-            let new_tag = generate_tag_msg(
-                MsgType::New,
-                &GitTag::Annotated(new_tag.clone()),
-                new_version,
-            );
-            println!("{new_tag}");
-            print_changelog(commit_msgs);
-        } else {
-            println!("New version: {new_version}\n");
-            println!("Command: \ngit tag -a {new_version} -s -m \"Release {new_version}\n");
-            print!("Changelog:");
-            for msg in commit_msgs {
-                print!("\n- {msg}");
-            }
-            println!("\"")
+    fn commit_all(repo: &Repository, message: &str) -> Oid {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = repo.signature().unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    async fn bump(dir: &Path, args: &[&str]) -> MietteResult<()> {
+        let args = ["tagge_rs", "bump", "--no-fetch", "--yes", "--sign", "none"]
+            .iter()
+            .chain(args);
+        let matches = CliArgs::command().try_get_matches_from(args).unwrap();
+        let mut cli_args = CliArgs::from_arg_matches(&matches).unwrap();
+        let args::Command::Bump(mut bump_args) = cli_args.take_command(&matches).unwrap() else {
+            panic!("Expected the bump command");
+        };
+        bump_args.resolve_level(&cli_args).unwrap();
+        let repo = Repository::open(dir).unwrap();
+        Session::new(repo, dir.to_path_buf(), cli_args)?
+            .bump(&bump_args)
+            .await
+    }
+
+    fn bump_level(args: &[&str]) -> Result<Option<VersionBump>, clap::Error> {
+        let matches = CliArgs::command().try_get_matches_from(args)?;
+        let mut cli_args = CliArgs::from_arg_matches(&matches)?;
+        let args::Command::Bump(mut bump_args) = cli_args.take_command(&matches)? else {
+            panic!("Expected the bump command");
+        };
+        bump_args.resolve_level(&cli_args)?;
+        Ok(bump_args.level)
+    }
+
+    #[test]
+    fn bump_level_defaults() {
+        let cases = [
+            (&["tagge_rs", "bump", "minor"][..], Some(VersionBump::Minor)),
+            (&["tagge_rs", "bump", "--use-pr"], Some(VersionBump::Auto)),
+            (&["tagge_rs", "--use-pr", "bump"], Some(VersionBump::Auto)),
+            (
+                &["tagge_rs", "bump", "patch", "--use-pr"],
+                Some(VersionBump::Patch),
+            ),
+            // --pre continues the pre-release, --tag needs no bump
+            (&["tagge_rs", "bump", "--pre", "rc", "--use-pr"], None),
+            (&["tagge_rs", "bump", "--tag", "v9.0.0"], None),
+        ];
+        for (args, level) in cases {
+            assert_eq!(bump_level(args).unwrap(), level, "{args:?}");
         }
-    } else {
-        print_changelog(commit_msgs);
+        assert!(bump_level(&["tagge_rs", "bump"]).is_err());
+    }
+
+    fn changed_paths(repo: &Repository, commit: &Commit) -> Vec<String> {
+        let parent_tree = commit.parent(0).unwrap().tree().unwrap();
+        let diff = repo
+            .diff_tree_to_tree(Some(&parent_tree), Some(&commit.tree().unwrap()), None)
+            .unwrap();
+        diff.deltas()
+            .filter_map(|delta| delta.new_file().path())
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn release_commit_contains_only_the_bumped_files() {
+        let (dir, repo) = released_repo("release-commit");
+        // Unrelated work in progress stays out of the release
+        std::fs::write(dir.join("README.md"), "# a\n\nWork in progress\n").unwrap();
+
+        bump(
+            &dir,
+            &["patch", "--version-file", "Cargo.toml", "--changelog"],
+        )
+        .await
+        .unwrap();
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.message(), Some("chore(release): v0.1.1"));
+        assert_eq!(changed_paths(&repo, &head), ["CHANGELOG.md", "Cargo.toml"]);
+        let manifest = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("version = \"0.1.1\""), "{manifest}");
+
+        let tag = repo
+            .revparse_single("refs/tags/v0.1.1")
+            .unwrap()
+            .peel_to_tag()
+            .unwrap();
+        assert_eq!(tag.target_id(), head.id());
+
+        let readme = std::fs::read_to_string(dir.join("README.md")).unwrap();
+        assert!(readme.contains("Work in progress"));
+        assert!(
+            repo.status_file(Path::new("README.md"))
+                .unwrap()
+                .is_wt_modified()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn release_rejects_dirty_files() {
+        let (dir, repo) = released_repo("release-dirty");
+        let head = repo.head().unwrap().target().unwrap();
+
+        // An uncommitted edit in a version file
+        let dirty =
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n";
+        std::fs::write(dir.join("Cargo.toml"), dirty).unwrap();
+        let error = bump(&dir, &["patch", "--version-file", "Cargo.toml"])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Cargo.toml"), "{error}");
+        assert_eq!(repo.head().unwrap().target().unwrap(), head);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("Cargo.toml")).unwrap(),
+            dirty
+        );
+
+        // An uncommitted edit in the changelog
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("CHANGELOG.md"), "# Changelog\n").unwrap();
+        commit_all(&repo, "docs: Add a changelog");
+        std::fs::write(dir.join("CHANGELOG.md"), "# Changelog\n\nDraft\n").unwrap();
+        let error = bump(&dir, &["patch", "--changelog"]).await.unwrap_err();
+        assert!(error.to_string().contains("CHANGELOG.md"), "{error}");
+
+        // Staged changes in any file
+        std::fs::write(dir.join("README.md"), "# b\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("README.md")).unwrap();
+        index.write().unwrap();
+        std::fs::write(dir.join("CHANGELOG.md"), "# Changelog\n").unwrap();
+        assert!(bump(&dir, &["patch", "--changelog"]).await.is_err());
+
+        assert_eq!(
+            repo.head().unwrap().peel_to_commit().unwrap().message(),
+            Some("docs: Add a changelog")
+        );
+        assert!(repo.find_reference("refs/tags/v0.1.1").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_push_writes_released_false() {
+        let (dir, _repo) = released_repo("failed-push");
+        let dotenv = dir.join("tagge.env");
+        let dotenv_arg = dotenv.display().to_string();
+
+        // There is no remote to push to
+        let error = bump(&dir, &["patch", "--push", "--dotenv", &dotenv_arg])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("push"), "{error}");
+        let outputs = std::fs::read_to_string(&dotenv).unwrap();
+        assert!(outputs.contains("TAG=v0.1.1\n"), "{outputs}");
+        assert!(outputs.contains("RELEASED=false\n"), "{outputs}");
+
+        bump(&dir, &["patch", "--dotenv", &dotenv_arg])
+            .await
+            .unwrap();
+        let outputs = std::fs::read_to_string(&dotenv).unwrap();
+        assert!(outputs.contains("RELEASED=true\n"), "{outputs}");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,341 @@
+use crate::changelog::Section;
+use crate::forge::PrInfo;
+use git2::Commit;
+use std::fmt::Write as FmtWrite;
+
+/// Release note groups for PR titles, picked from the PR labels, in the order they are written.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LabelGroup {
+    Breaking,
+    Features,
+    BugFixes,
+    Other,
+}
+
+impl LabelGroup {
+    /// The first group with a matching label, labels are compared case-insensitively.
+    pub fn of(labels: &[String]) -> Self {
+        [Self::Breaking, Self::Features, Self::BugFixes]
+            .into_iter()
+            .find(|group| {
+                labels
+                    .iter()
+                    .any(|label| group.labels().contains(&label.to_lowercase().as_str()))
+            })
+            .unwrap_or(Self::Other)
+    }
+
+    fn labels(&self) -> &'static [&'static str] {
+        match self {
+            Self::Breaking => &["breaking", "breaking-change", "breaking change"],
+            Self::Features => &["enhancement", "feature"],
+            Self::BugFixes => &["bug", "bugfix"],
+            Self::Other => &[],
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Breaking => "Breaking changes",
+            Self::Features => "Features",
+            Self::BugFixes => "Bug fixes",
+            Self::Other => "Other changes",
+        }
+    }
+
+    /// Keep a Changelog section that overrides the one guessed from the title.
+    pub fn section(&self) -> Option<Section> {
+        match self {
+            Self::Breaking => Some(Section::Changed),
+            Self::Features => Some(Section::Added),
+            Self::BugFixes => Some(Section::Fixed),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Release note entries under an optional heading.
+pub struct NoteGroup {
+    pub title: Option<&'static str>,
+    pub entries: Vec<String>,
+}
+
+/// Pair each commit with its PR and keep only the newest commit of every PR,
+/// so a PR with several commits becomes a single entry.
+pub fn collapse_prs<'a, 'r>(
+    commits: &'a [Commit<'r>],
+    prs: &'a [(String, Option<PrInfo>)],
+) -> Vec<(&'a Commit<'r>, Option<&'a PrInfo>)> {
+    let mut seen = Vec::new();
+    commits
+        .iter()
+        .map(|c| {
+            let commit_id = c.id().to_string();
+            let pr = prs
+                .iter()
+                .find(|(sha, _)| *sha == commit_id)
+                .and_then(|(_, pr)| pr.as_ref());
+            (c, pr)
+        })
+        .filter(|(_, pr)| match pr {
+            Some(pr) if seen.contains(&pr.number) => false,
+            Some(pr) => {
+                seen.push(pr.number);
+                true
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// Entry for a PR: "<title> (#123) @author".
+pub fn pr_entry(pr: &PrInfo, title: &str) -> String {
+    let mut entry = format!("{title} ({})", pr.reference);
+    if let Some(author) = &pr.author {
+        write!(entry, " @{author}").expect("Should never fail");
+    }
+    entry
+}
+
+/// Group the entries by label group, skipping empty groups.
+pub fn group_by_label(entries: impl Iterator<Item = (LabelGroup, String)>) -> Vec<NoteGroup> {
+    let mut entries: Vec<_> = entries.collect();
+    // Stable sort keeps the commit order within a group
+    entries.sort_by_key(|(group, _)| *group);
+
+    let mut groups: Vec<(LabelGroup, NoteGroup)> = Vec::new();
+    for (group, entry) in entries {
+        match groups.last_mut() {
+            Some((last, note_group)) if *last == group => note_group.entries.push(entry),
+            _ => groups.push((
+                group,
+                NoteGroup {
+                    title: Some(group.title()),
+                    entries: vec![entry],
+                },
+            )),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Render the notes as Markdown for a release body.
+pub fn to_markdown(groups: &[NoteGroup]) -> String {
+    let mut body = String::new();
+    for group in groups {
+        if let Some(title) = group.title {
+            if !body.is_empty() {
+                body.push('\n');
+            }
+            writeln!(body, "### {title}\n").expect("Should never fail");
+        }
+        for entry in &group.entries {
+            writeln!(body, "- {entry}").expect("Should never fail");
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Oid;
+    use git2::Repository;
+
+    fn pr(number: u64, title: &str, labels: &[&str]) -> PrInfo {
+        PrInfo {
+            number,
+            reference: format!("#{number}"),
+            title: title.to_string(),
+            author: Some("octocat".to_string()),
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+        }
+    }
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    /// A repository with one empty commit per summary, returned newest first.
+    fn repo_with_commits(name: &str, summaries: &[&str]) -> (std::path::PathBuf, Repository) {
+        let dir = std::env::temp_dir().join(format!("tagge-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        {
+            let signature = git2::Signature::now("Tagge", "tagge@example.com").unwrap();
+            let tree_id = repo.index().unwrap().write_tree().unwrap();
+            let tree = repo.find_tree(tree_id).unwrap();
+            let mut parent: Option<Oid> = None;
+            for summary in summaries {
+                let parents: Vec<_> = parent
+                    .map(|p| repo.find_commit(p).unwrap())
+                    .into_iter()
+                    .collect();
+                let parents: Vec<_> = parents.iter().collect();
+                parent = Some(
+                    repo.commit(
+                        Some("HEAD"),
+                        &signature,
+                        &signature,
+                        summary,
+                        &tree,
+                        &parents,
+                    )
+                    .unwrap(),
+                );
+            }
+        }
+        (dir, repo)
+    }
+
+    fn commits(repo: &Repository) -> Vec<Commit<'_>> {
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push_head().unwrap();
+        revwalk
+            .map(|oid| repo.find_commit(oid.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn label_groups() {
+        let cases = [
+            (labels(&["bug", "breaking"]), LabelGroup::Breaking),
+            (labels(&["Enhancement"]), LabelGroup::Features),
+            (labels(&["documentation", "bugfix"]), LabelGroup::BugFixes),
+            (labels(&["Breaking Change"]), LabelGroup::Breaking),
+            (labels(&["documentation"]), LabelGroup::Other),
+            (labels(&[]), LabelGroup::Other),
+        ];
+        for (labels, group) in cases {
+            assert_eq!(LabelGroup::of(&labels), group, "{labels:?}");
+        }
+    }
+
+    #[test]
+    fn group_by_label_order() {
+        let groups = group_by_label(
+            [
+                (LabelGroup::Other, "Docs"),
+                (LabelGroup::BugFixes, "Crash"),
+                (LabelGroup::Features, "Flag"),
+                (LabelGroup::BugFixes, "Typo"),
+                (LabelGroup::Breaking, "New API"),
+            ]
+            .into_iter()
+            .map(|(group, entry)| (group, entry.to_string())),
+        );
+        let groups: Vec<_> = groups
+            .iter()
+            .map(|group| (group.title, group.entries.clone()))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (Some("Breaking changes"), vec!["New API".to_string()]),
+                (Some("Features"), vec!["Flag".to_string()]),
+                // The commit order is kept within a group
+                (
+                    Some("Bug fixes"),
+                    vec!["Crash".to_string(), "Typo".to_string()]
+                ),
+                (Some("Other changes"), vec!["Docs".to_string()]),
+            ]
+        );
+
+        // Only the groups with entries are written
+        let groups = group_by_label([(LabelGroup::Other, "Docs".to_string())].into_iter());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].title, Some("Other changes"));
+    }
+
+    #[test]
+    fn pr_entries() {
+        assert_eq!(
+            pr_entry(&pr(12, "Add a flag", &[]), "Add a flag"),
+            "Add a flag (#12) @octocat"
+        );
+        let mut anonymous = pr(7, "Fix", &[]);
+        anonymous.author = None;
+        anonymous.reference = "!7".to_string();
+        assert_eq!(pr_entry(&anonymous, "Fix"), "Fix (!7)");
+    }
+
+    #[test]
+    fn collapse_prs_with_several_commits() {
+        let (dir, repo) = repo_with_commits(
+            "collapse-prs",
+            &[
+                "Start the flag",
+                "Direct push",
+                "Finish the flag",
+                "Fix a crash",
+            ],
+        );
+        let commits = commits(&repo);
+        let sha = |summary: &str| {
+            commits
+                .iter()
+                .find(|c| c.summary() == Some(summary))
+                .unwrap()
+                .id()
+                .to_string()
+        };
+        let prs = vec![
+            (
+                sha("Start the flag"),
+                Some(pr(1, "Add a flag", &["feature"])),
+            ),
+            (sha("Direct push"), None),
+            (
+                sha("Finish the flag"),
+                Some(pr(1, "Add a flag", &["feature"])),
+            ),
+            (sha("Fix a crash"), Some(pr(2, "Fix a crash", &["bug"]))),
+        ];
+
+        let entries: Vec<_> = collapse_prs(&commits, &prs)
+            .into_iter()
+            .map(|(c, pr)| (c.summary().unwrap().to_string(), pr.map(|pr| pr.number)))
+            .collect();
+        // Newest first, only the newest commit of PR #1 is kept
+        assert_eq!(
+            entries,
+            [
+                ("Fix a crash".to_string(), Some(2)),
+                ("Finish the flag".to_string(), Some(1)),
+                ("Direct push".to_string(), None),
+            ]
+        );
+        drop(commits);
+        drop(repo);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pr_titles_markdown() {
+        let prs = [
+            pr(3, "Fix a crash", &["bug"]),
+            pr(4, "Add a flag", &["enhancement"]),
+            pr(5, "Update the docs", &["documentation"]),
+        ];
+        let groups = group_by_label(
+            prs.iter()
+                .map(|pr| (LabelGroup::of(&pr.labels), pr_entry(pr, &pr.title)))
+                .chain([(LabelGroup::Other, "Direct push".to_string())]),
+        );
+        assert_eq!(
+            to_markdown(&groups),
+            "### Features\n\n- Add a flag (#4) @octocat\n\n\
+             ### Bug fixes\n\n- Fix a crash (#3) @octocat\n\n\
+             ### Other changes\n\n- Update the docs (#5) @octocat\n- Direct push\n"
+        );
+
+        // Without PR titles the notes are an untitled list
+        let plain = [NoteGroup {
+            title: None,
+            entries: vec!["a1b2c3d Fix a crash (#3)".to_string()],
+        }];
+        assert_eq!(to_markdown(&plain), "- a1b2c3d Fix a crash (#3)\n");
+        assert_eq!(to_markdown(&[]), "");
+    }
+}
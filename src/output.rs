@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+/// Format of the results on stdout.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable, colored text
+    #[default]
+    Text,
+    /// A single JSON document, messages go to stderr and errors are JSON too
+    Json,
+}
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// Set the format once, right after parsing the arguments. Errors are rendered as JSON from then on.
+pub fn init(format: OutputFormat) {
+    FORMAT.set(format).expect("Output format is set once");
+    if format == OutputFormat::Json {
+        miette::set_hook(Box::new(|_| Box::new(miette::JSONReportHandler::new())))
+            .expect("No other miette hook is installed");
+    }
+}
+
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// `println!` for messages, written to stderr in JSON mode to keep stdout a valid document.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::output::is_json() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use say;
+
+/// Print a result as pretty JSON on stdout.
+pub fn print_json(value: &impl Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("Results are always serializable")
+    );
+}
+
+/// Result of `show`, `bump`, `init` and `changelog`.
+#[derive(Serialize)]
+pub struct Report {
+    pub latest_tag: Option<String>,
+    pub latest_version: Option<String>,
+    /// Commit the latest tag points to
+    pub latest_sha: Option<String>,
+    /// Range of the commits, `from` is `None` when they start at the first commit
+    pub from: Option<String>,
+    pub to: String,
+    pub new_version: Option<String>,
+    /// Object ID of the created tag, `None` in a dry run
+    pub new_tag_sha: Option<String>,
+    pub dry_run: bool,
+    pub commits: Vec<CommitInfo>,
+    /// Release notes as Markdown
+    pub changelog: String,
+}
+
+#[derive(Serialize)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub summary: String,
+    pub author: Option<String>,
+    /// Number of the PR or MR that merged the commit, with --use-pr
+    pub pr: Option<u64>,
+    pub pr_author: Option<String>,
+}
+
+/// A tag of `list`.
+#[derive(Serialize)]
+pub struct TagEntry {
+    pub name: String,
+    pub version: String,
+    pub sha: String,
+    pub date: String,
+    pub annotated: bool,
+}
+
+/// Result of `verify`.
+#[derive(Serialize)]
+pub struct Verification {
+    pub tag: String,
+    pub sha: String,
+    pub checks: Vec<Check>,
+    pub valid: bool,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub check: String,
+    /// `None` for a check that was skipped, like the signature of an unsigned tag
+    pub ok: Option<bool>,
+}
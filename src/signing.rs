@@ -0,0 +1,288 @@
+use clap::ValueEnum;
+use git2::Config;
+use git2::Repository;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use serde::Deserialize;
+use std::fmt;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+
+/// How the new tag is signed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignMode {
+    /// Annotated tag without a signature
+    None,
+    /// OpenPGP signature made with gpg
+    Gpg,
+    /// SSH signature made with ssh-keygen (`gpg.format=ssh`)
+    Ssh,
+    /// X.509 signature made with gpgsm (`gpg.format=x509`)
+    X509,
+}
+
+impl SignMode {
+    /// Without `--sign`, sign like `git tag -a` would: with `gpg.format` if
+    /// `tag.gpgSign` is set, otherwise not at all.
+    pub fn from_git_config(repo: &Repository) -> Self {
+        let Ok(config) = repo.config() else {
+            return Self::None;
+        };
+        if !config.get_bool("tag.gpgSign").unwrap_or(false) {
+            return Self::None;
+        }
+        match config.get_string("gpg.format").as_deref() {
+            Ok("ssh") => Self::Ssh,
+            Ok("x509") => Self::X509,
+            _ => Self::Gpg,
+        }
+    }
+
+    /// Value of `gpg.format` for this mode.
+    pub fn gpg_format(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gpg => Some("openpgp"),
+            Self::Ssh => Some("ssh"),
+            Self::X509 => Some("x509"),
+        }
+    }
+
+    /// The equivalent `git tag` command, shown in dry runs.
+    pub fn git_tag_command(&self, tag_name: &str) -> String {
+        match self.gpg_format() {
+            Some(format) => format!("git -c gpg.format={format} tag -s {tag_name}"),
+            None => format!("git tag -a {tag_name}"),
+        }
+    }
+
+    fn program_config(&self) -> &'static [&'static str] {
+        match self {
+            Self::None => &[],
+            Self::Gpg => &["gpg.openpgp.program", "gpg.program"],
+            Self::Ssh => &["gpg.ssh.program"],
+            Self::X509 => &["gpg.x509.program"],
+        }
+    }
+
+    fn default_program(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Gpg => "gpg",
+            Self::Ssh => "ssh-keygen",
+            Self::X509 => "gpgsm",
+        }
+    }
+}
+
+impl Display for SignMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Gpg => write!(f, "gpg"),
+            Self::Ssh => write!(f, "ssh"),
+            Self::X509 => write!(f, "x509"),
+        }
+    }
+}
+
+/// A signing mode whose program and key were found.
+pub struct Signer {
+    pub mode: SignMode,
+    /// `user.signingKey`, the default key of the program if unset
+    pub key: Option<String>,
+    pub program: String,
+    /// SSH key file, `None` for a literal `key::` public key
+    ssh_key_file: Option<PathBuf>,
+}
+
+impl Signer {
+    /// Check that the signing program and key are available, before anything is tagged.
+    pub fn new(repo: &Repository, mode: SignMode) -> MietteResult<Self> {
+        let config = repo.config().into_diagnostic()?;
+        let key = config.get_string("user.signingKey").ok();
+        let program = mode
+            .program_config()
+            .iter()
+            .find_map(|name| config.get_string(name).ok())
+            .unwrap_or_else(|| mode.default_program().to_string());
+        let mut signer = Self {
+            mode,
+            key,
+            program,
+            ssh_key_file: None,
+        };
+
+        match mode {
+            SignMode::None => {}
+            SignMode::Gpg | SignMode::X509 => {
+                signer.check_program()?;
+                signer.check_secret_key()?;
+            }
+            SignMode::Ssh => {
+                signer.check_program()?;
+                signer.ssh_key_file = signer.check_ssh_key(&config)?;
+            }
+        }
+        tracing::info!(
+            "Signing mode: {mode}, program: {}, key: {}",
+            signer.program,
+            signer.key.as_deref().unwrap_or("<default>")
+        );
+        Ok(signer)
+    }
+
+    fn check_program(&self) -> MietteResult<()> {
+        if find_program(&self.program).is_none() {
+            return Err(miette!(
+                help = "Install it, point git to it with gpg.program, gpg.ssh.program or gpg.x509.program, or use --sign none.",
+                "Signing program '{}' for --sign {} not found",
+                self.program,
+                self.mode
+            ));
+        }
+        Ok(())
+    }
+
+    /// gpg and gpgsm list nothing if there is no usable secret key.
+    fn check_secret_key(&self) -> MietteResult<()> {
+        let mut command = Command::new(&self.program);
+        command.arg("--list-secret-keys");
+        command.args(&self.key);
+        let output = command.output().into_diagnostic()?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(miette!(
+                help = "Create or import a key, set user.signingKey, or use --sign none.",
+                "No {} signing key {}found",
+                self.mode,
+                self.key
+                    .as_ref()
+                    .map(|key| format!("'{key}' "))
+                    .unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+
+    /// SSH signing needs `user.signingKey`, a key file or a literal `key::` public key.
+    fn check_ssh_key(&self, config: &Config) -> MietteResult<Option<PathBuf>> {
+        let Some(key) = &self.key else {
+            return Err(miette!(
+                help = "Set it with `git config user.signingKey ~/.ssh/id_ed25519.pub`, or use --sign none.",
+                "SSH signing needs user.signingKey"
+            ));
+        };
+        if key.starts_with("key::") || key.starts_with("ssh-") {
+            return Ok(None);
+        }
+        let path = config
+            .get_path("user.signingKey")
+            .unwrap_or_else(|_| PathBuf::from(key));
+        if !path.is_file() {
+            return Err(miette!(
+                help = "Set user.signingKey to an existing key file, or use --sign none.",
+                "SSH signing key {} not found",
+                path.display()
+            ));
+        }
+        Ok(Some(path))
+    }
+
+    /// Detached, ASCII-armored signature of `payload`, made with the same
+    /// program and arguments as `git tag -s`.
+    pub fn sign(&self, payload: &[u8]) -> MietteResult<Vec<u8>> {
+        let mut command = Command::new(&self.program);
+        // ssh-keygen needs a file, a literal public key is written to a temporary one
+        let mut literal_key_file = None;
+        match self.mode {
+            SignMode::None => return Err(miette!("Signing mode none cannot sign")),
+            SignMode::Gpg | SignMode::X509 => {
+                command.arg("-bsa");
+                if let Some(key) = &self.key {
+                    command.args(["-u", key]);
+                }
+            }
+            SignMode::Ssh => {
+                let key_file = match &self.ssh_key_file {
+                    Some(path) => path.clone(),
+                    None => {
+                        let key = self.key.as_deref().unwrap_or_default();
+                        let path = std::env::temp_dir()
+                            .join(format!("tagge-signing-key-{}.pub", std::process::id()));
+                        std::fs::write(&path, key.trim_start_matches("key::"))
+                            .into_diagnostic()
+                            .wrap_err("Failed to write the SSH public key")?;
+                        literal_key_file = Some(path.clone());
+                        path
+                    }
+                };
+                command
+                    .args(["-Y", "sign", "-n", "git", "-f"])
+                    .arg(key_file);
+            }
+        }
+
+        tracing::info!("Signing the tag with {:?}", command);
+        let output = run_with_input(command, payload);
+        if let Some(path) = literal_key_file {
+            let _ = std::fs::remove_file(path);
+        }
+        let output = output
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to run {}", self.program))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(miette!(
+                "{} failed to sign the tag:\n{}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(output.stdout)
+    }
+}
+
+fn run_with_input(mut command: Command, input: &[u8]) -> std::io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input)?;
+    child.wait_with_output()
+}
+
+/// Resolve a program name like the shell does, paths are taken as they are.
+fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) {
+        let path = PathBuf::from(program);
+        return path.is_file().then_some(path);
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file() || path.with_extension("exe").is_file()
+    }
+}
@@ -1,4 +1,14 @@
+use crate::args::PreRelease;
+use crate::args::VersionBump;
+use chrono::Utc;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use semver::BuildMetadata;
+use semver::Prerelease;
 use semver::Version;
+use std::cmp::Ordering;
 
 pub trait ToVString {
     fn to_v_string(&self) -> String;
@@ -9,3 +19,351 @@ impl ToVString for Version {
         format!("v{self}")
     }
 }
+
+/// Split a pre-release such as `rc.2` into its channel and counter.
+pub fn prerelease_parts(pre: &Prerelease) -> Option<(&str, u64)> {
+    let (channel, counter) = pre.as_str().split_once('.')?;
+    Some((channel, counter.parse().ok()?))
+}
+
+pub fn make_prerelease(channel: &str, counter: u64) -> Prerelease {
+    Prerelease::new(&format!("{channel}.{counter}"))
+        .expect("Channel names and counters are valid pre-release identifiers")
+}
+
+/// The version after `latest_version`, bumped by `bump` and/or moved to the pre-release
+/// channel `pre`. Fails if it would not be greater than `latest_version`.
+pub fn bump_version(
+    latest_version: &Version,
+    bump: Option<&VersionBump>,
+    pre: Option<&PreRelease>,
+) -> MietteResult<Version> {
+    let mut new_version = latest_version.clone();
+    new_version.build = BuildMetadata::EMPTY;
+
+    // Without a new pre-release channel, bumping a pre-release to the level it
+    // already anticipates only drops the pre-release (v1.1.0-rc.1 -> minor -> v1.1.0)
+    let absorbs =
+        |anticipated: bool| pre.is_none() && !latest_version.pre.is_empty() && anticipated;
+
+    match bump {
+        Some(VersionBump::Major) if !absorbs(new_version.minor == 0 && new_version.patch == 0) => {
+            new_version.major += 1;
+            new_version.minor = 0;
+            new_version.patch = 0;
+        }
+        Some(VersionBump::Minor) if !absorbs(new_version.patch == 0) => {
+            new_version.minor += 1;
+            new_version.patch = 0;
+        }
+        Some(VersionBump::Patch) if !absorbs(true) => {
+            new_version.patch += 1;
+        }
+        Some(VersionBump::Release) if latest_version.pre.is_empty() => {
+            return Err(miette!(
+                help = "Use --pre to start a pre-release.",
+                "Latest version {} is not a pre-release, nothing to promote!",
+                latest_version.to_v_string()
+            ));
+        }
+        Some(VersionBump::Auto) => unreachable!("Auto bump must be resolved before bumping!"),
+        // Absorbed bumps, promotions and pre-release only changes keep the version core
+        _ => {}
+    }
+
+    new_version.pre = match pre {
+        Some(pre) => {
+            let channel = pre.as_str();
+            let counter = match (bump, prerelease_parts(&latest_version.pre)) {
+                // Continue the latest pre-release line (rc.1 -> rc.2)
+                (None, Some((latest_channel, counter))) if latest_channel == channel => counter + 1,
+                // Start a pre-release for the next patch of a final version
+                (None, _) if latest_version.pre.is_empty() => {
+                    new_version.patch += 1;
+                    1
+                }
+                // New version core or new channel (beta -> rc)
+                _ => 1,
+            };
+            make_prerelease(channel, counter)
+        }
+        None => Prerelease::EMPTY,
+    };
+
+    ensure_greater(&new_version, latest_version)?;
+    Ok(new_version)
+}
+
+/// Fail if `new_version` would not sort after `latest_version`, build metadata is ignored.
+pub fn ensure_greater(new_version: &Version, latest_version: &Version) -> MietteResult<()> {
+    if new_version.cmp_precedence(latest_version) != Ordering::Greater {
+        return Err(miette!(
+            "New version {} would not be greater than the latest version {}!",
+            new_version.to_v_string(),
+            latest_version.to_v_string()
+        ));
+    }
+    Ok(())
+}
+
+/// Expand a build metadata template such as `build.{env:BUILD_NUMBER}` or `{sha}.{date}`.
+///
+/// Placeholders:
+///  - `{sha}`: short SHA of HEAD
+///  - `{date}`: current UTC date as YYYYMMDD
+///  - `{env:NAME}`: value of the environment variable NAME, looked up with `env`
+pub fn expand_metadata(
+    template: &str,
+    head_sha: &str,
+    env: impl Fn(&str) -> Option<String>,
+) -> MietteResult<BuildMetadata> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| miette!("Unclosed placeholder in metadata template '{template}'"))?;
+
+        match &rest[start + 1..end] {
+            "sha" => expanded.push_str(&head_sha[..7.min(head_sha.len())]),
+            "date" => expanded.push_str(&Utc::now().format("%Y%m%d").to_string()),
+            placeholder => {
+                let Some(name) = placeholder.strip_prefix("env:") else {
+                    return Err(miette!(
+                        help = "Supported placeholders: {sha}, {date}, {env:NAME}",
+                        "Unknown placeholder '{{{placeholder}}}' in metadata template"
+                    ));
+                };
+                let value = env(name)
+                    .ok_or_else(|| miette!("Metadata placeholder references unset {name}"))?;
+                expanded.push_str(&value);
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    BuildMetadata::new(expanded.trim_start_matches('+'))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid build metadata '{expanded}'"))
+}
+
+/// Append the free-form `--suffix` to the pre-release part of the version.
+pub fn apply_suffix(version: &mut Version, suffix: &str) -> MietteResult<()> {
+    let pre = if version.pre.is_empty() {
+        suffix.to_string()
+    } else {
+        format!("{}-{suffix}", version.pre)
+    };
+    version.pre = Prerelease::new(&pre)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid tag suffix '{suffix}'"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bump(
+        latest: &str,
+        bump: Option<VersionBump>,
+        pre: Option<PreRelease>,
+    ) -> MietteResult<String> {
+        let latest = Version::parse(latest).unwrap();
+        bump_version(&latest, bump.as_ref(), pre.as_ref()).map(|version| version.to_string())
+    }
+
+    fn no_env(_name: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn bump_final_versions() {
+        let cases = [
+            ("1.2.3", Some(VersionBump::Patch), None, "1.2.4"),
+            ("1.2.3", Some(VersionBump::Minor), None, "1.3.0"),
+            ("1.2.3", Some(VersionBump::Major), None, "2.0.0"),
+            ("1.2.3+build.7", Some(VersionBump::Patch), None, "1.2.4"),
+            // A pre-release of a final version starts at the next patch
+            ("1.2.3", None, Some(PreRelease::Rc), "1.2.4-rc.1"),
+            (
+                "1.2.3",
+                Some(VersionBump::Minor),
+                Some(PreRelease::Beta),
+                "1.3.0-beta.1",
+            ),
+            (
+                "1.2.3",
+                Some(VersionBump::Major),
+                Some(PreRelease::Alpha),
+                "2.0.0-alpha.1",
+            ),
+        ];
+        for (latest, level, pre, expected) in cases {
+            assert_eq!(
+                bump(latest, level, pre).unwrap(),
+                expected,
+                "{latest} {level:?} {pre:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bump_pre_releases() {
+        let cases = [
+            // Bumps the pre-release already anticipates are absorbed
+            ("1.3.0-rc.1", Some(VersionBump::Patch), None, "1.3.0"),
+            ("1.3.0-rc.1", Some(VersionBump::Minor), None, "1.3.0"),
+            ("2.0.0-beta.2", Some(VersionBump::Major), None, "2.0.0"),
+            // Larger bumps are not
+            ("1.3.1-rc.1", Some(VersionBump::Minor), None, "1.4.0"),
+            ("1.3.0-rc.1", Some(VersionBump::Major), None, "2.0.0"),
+            // Nor are bumps that start a new pre-release
+            (
+                "1.3.0-rc.1",
+                Some(VersionBump::Minor),
+                Some(PreRelease::Rc),
+                "1.4.0-rc.1",
+            ),
+            // Continue the channel
+            ("1.3.0-rc.1", None, Some(PreRelease::Rc), "1.3.0-rc.2"),
+            ("1.3.0-rc.9", None, Some(PreRelease::Rc), "1.3.0-rc.10"),
+            // Switch to a later channel
+            ("1.3.0-beta.3", None, Some(PreRelease::Rc), "1.3.0-rc.1"),
+            (
+                "1.3.0-alpha.2",
+                None,
+                Some(PreRelease::Beta),
+                "1.3.0-beta.1",
+            ),
+            // Promote to the final version
+            ("1.3.0-rc.2", Some(VersionBump::Release), None, "1.3.0"),
+            (
+                "1.3.0-rc.2+build.1",
+                Some(VersionBump::Release),
+                None,
+                "1.3.0",
+            ),
+        ];
+        for (latest, level, pre, expected) in cases {
+            assert_eq!(
+                bump(latest, level, pre).unwrap(),
+                expected,
+                "{latest} {level:?} {pre:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bump_errors() {
+        let cases = [
+            // Channel downgrades would not be greater
+            ("1.3.0-rc.1", None, Some(PreRelease::Beta)),
+            ("1.3.0-beta.1", None, Some(PreRelease::Alpha)),
+            // Nothing to promote
+            ("1.3.0", Some(VersionBump::Release), None),
+            (
+                "1.3.0-rc.1",
+                Some(VersionBump::Release),
+                Some(PreRelease::Rc),
+            ),
+        ];
+        for (latest, level, pre) in cases {
+            assert!(
+                bump(latest, level, pre).is_err(),
+                "{latest} {level:?} {pre:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn suffix_below_latest_pre_release() {
+        let latest = Version::parse("0.3.0-rc.1").unwrap();
+        let mut new_version = bump_version(&latest, Some(&VersionBump::Patch), None).unwrap();
+        assert!(ensure_greater(&new_version, &latest).is_ok());
+
+        // 0.3.0-foo sorts below 0.3.0-rc.1
+        apply_suffix(&mut new_version, "foo").unwrap();
+        assert!(ensure_greater(&new_version, &latest).is_err());
+
+        let mut new_version = bump_version(&latest, None, Some(&PreRelease::Rc)).unwrap();
+        apply_suffix(&mut new_version, "hotfix").unwrap();
+        assert_eq!(new_version.to_string(), "0.3.0-rc.2-hotfix");
+        assert!(ensure_greater(&new_version, &latest).is_ok());
+    }
+
+    #[test]
+    fn metadata_templates() {
+        let sha = "82ca80b5f1e2d3c4";
+        let cases = [
+            ("build.42", "build.42"),
+            ("{sha}", "82ca80b"),
+            ("build.{sha}", "build.82ca80b"),
+            // A leading + is the separator, not part of the metadata
+            ("+build.{sha}", "build.82ca80b"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(
+                expand_metadata(template, sha, no_env).unwrap().as_str(),
+                expected,
+                "{template}"
+            );
+        }
+
+        let date = expand_metadata("{date}", sha, no_env).unwrap();
+        assert_eq!(date.len(), 8);
+        assert!(date.chars().all(|c| c.is_ascii_digit()));
+
+        let env = |name: &str| (name == "BUILD_NUMBER").then(|| "17".to_string());
+        assert_eq!(
+            expand_metadata("build.{env:BUILD_NUMBER}", sha, env)
+                .unwrap()
+                .as_str(),
+            "build.17"
+        );
+    }
+
+    #[test]
+    fn metadata_template_errors() {
+        let sha = "82ca80b5f1e2d3c4";
+        let cases = [
+            "build.{sha",
+            "{",
+            "{commit}",
+            "{env}",
+            "{env:BUILD_NUMBER}",
+            "build_42",
+            "build..42",
+        ];
+        for template in cases {
+            assert!(
+                expand_metadata(template, sha, no_env).is_err(),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn suffixes() {
+        let cases = [
+            ("1.2.3", "hotfix", "1.2.3-hotfix"),
+            ("1.2.3", "build.1", "1.2.3-build.1"),
+            // Appended to an existing pre-release
+            ("1.3.0-rc.1", "hotfix", "1.3.0-rc.1-hotfix"),
+        ];
+        for (version, suffix, expected) in cases {
+            let mut version = Version::parse(version).unwrap();
+            apply_suffix(&mut version, suffix).unwrap();
+            assert_eq!(version.to_string(), expected, "{suffix}");
+        }
+
+        for suffix in ["hot fix", "hot_fix", "a..b", "ü"] {
+            let mut version = Version::parse("1.2.3").unwrap();
+            assert!(apply_suffix(&mut version, suffix).is_err(), "{suffix}");
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use crate::output::say;
+use colored::Colorize;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::Result as MietteResult;
+use miette::miette;
+use regex::Regex;
+use serde::Deserialize;
+use similar::ChangeTag;
+use similar::TextDiff;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use toml_edit::DocumentMut;
+
+/// A file containing the project version, e.g. `Cargo.toml` or `package.json`.
+/// Files without a built-in handler need a `pattern` with a `version` capture group.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "VersionFileDef")]
+pub struct VersionFile {
+    pub path: PathBuf,
+    pub pattern: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VersionFileDef {
+    Path(PathBuf),
+    Full {
+        path: PathBuf,
+        pattern: Option<String>,
+    },
+}
+
+impl From<VersionFileDef> for VersionFile {
+    fn from(def: VersionFileDef) -> Self {
+        match def {
+            VersionFileDef::Path(path) => Self {
+                path,
+                pattern: None,
+            },
+            VersionFileDef::Full { path, pattern } => Self { path, pattern },
+        }
+    }
+}
+
+impl VersionFile {
+    pub fn parse_arg(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            path: PathBuf::from(path),
+            pattern: None,
+        })
+    }
+
+    fn file_name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+    }
+}
+
+/// New contents for a file in the repository, relative to the work directory.
+pub struct FileUpdate {
+    pub path: PathBuf,
+    pub old: String,
+    pub new: String,
+}
+
+impl FileUpdate {
+    pub fn write(&self, workdir: &Path) -> MietteResult<()> {
+        let full_path = workdir.join(&self.path);
+        std::fs::write(&full_path, &self.new)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write {}", full_path.display()))?;
+        tracing::info!("Updated {}", full_path.display());
+        Ok(())
+    }
+
+    pub fn print_diff(&self) {
+        let path = self.path.display();
+        say!("{}", format!("--- a/{path}\n+++ b/{path}").bold());
+        let diff = TextDiff::from_lines(&self.old, &self.new);
+        for hunk in diff.unified_diff().context_radius(2).iter_hunks() {
+            say!("{}", hunk.header().to_string().cyan());
+            for change in hunk.iter_changes() {
+                let line = change.to_string_lossy();
+                let line = line.trim_end_matches('\n');
+                match change.tag() {
+                    ChangeTag::Delete => say!("{}", format!("-{line}").red()),
+                    ChangeTag::Insert => say!("{}", format!("+{line}").green()),
+                    ChangeTag::Equal => say!(" {line}"),
+                }
+            }
+        }
+        say!();
+    }
+}
+
+/// Compute the update that sets the version in `file`.
+pub fn bump_file(workdir: &Path, file: &VersionFile, version: &str) -> MietteResult<FileUpdate> {
+    let full_path = workdir.join(&file.path);
+    let old = std::fs::read_to_string(&full_path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read version file {}", full_path.display()))?;
+
+    let new = set_version(file, &old, version)?;
+
+    Ok(FileUpdate {
+        path: file.path.clone(),
+        old,
+        new,
+    })
+}
+
+/// `contents` of `file` with the version set to `version`.
+pub fn set_version(file: &VersionFile, contents: &str, version: &str) -> MietteResult<String> {
+    match (&file.pattern, file.file_name()) {
+        (Some(pattern), _) => replace_pattern(contents, pattern, version),
+        (None, "Cargo.toml") => set_toml_version(
+            contents,
+            &[
+                &["package", "version"],
+                &["workspace", "package", "version"],
+            ],
+            version,
+        ),
+        (None, "pyproject.toml") => set_toml_version(
+            contents,
+            &[&["project", "version"], &["tool", "poetry", "version"]],
+            version,
+        ),
+        (None, "package.json") => set_package_json_version(contents, version),
+        (None, _) => Err(miette!(
+            help = "Add a `pattern` with a `version` capture group for this file in the config.",
+            "No built-in version handler for this file"
+        )),
+    }
+    .wrap_err_with(|| format!("Failed to update version in {}", file.path.display()))
+}
+
+/// Lockfile of a manifest and the command that refreshes it.
+struct Lockfile {
+    path: PathBuf,
+    program: &'static str,
+    args: &'static [&'static str],
+}
+
+impl Lockfile {
+    /// The lockfile belonging to a manifest, if the manifest type has one and it exists.
+    /// Workspaces share one lockfile at their root, so it is looked up from the directory
+    /// of the manifest up to the work directory.
+    fn of(workdir: &Path, file: &VersionFile) -> Option<Self> {
+        let (name, program, args): (_, _, &'static [&'static str]) = match file.file_name() {
+            "Cargo.toml" => (
+                "Cargo.lock",
+                "cargo",
+                &["update", "--workspace", "--offline"],
+            ),
+            "package.json" => (
+                "package-lock.json",
+                "npm",
+                &["install", "--package-lock-only", "--ignore-scripts"],
+            ),
+            "pyproject.toml" => ("uv.lock", "uv", &["lock"]),
+            _ => return None,
+        };
+        let path = file
+            .path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .ancestors()
+            .map(|dir| dir.join(name))
+            .find(|path| workdir.join(path).is_file())?;
+        Some(Self {
+            path,
+            program,
+            args,
+        })
+    }
+
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    fn command(&self) -> String {
+        format!("{} {}", self.program, self.args.join(" "))
+    }
+}
+
+/// Refresh the lockfile belonging to an updated manifest, if there is one.
+/// Returns the path of the lockfile relative to the work directory.
+pub fn refresh_lockfile(workdir: &Path, file: &VersionFile) -> MietteResult<Option<PathBuf>> {
+    let Some(lockfile) = Lockfile::of(workdir, file) else {
+        return Ok(None);
+    };
+
+    tracing::info!(
+        "Refreshing {} with {}",
+        lockfile.path.display(),
+        lockfile.program
+    );
+    let output = Command::new(lockfile.program)
+        .args(lockfile.args)
+        .current_dir(workdir.join(lockfile.dir()))
+        .output()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to run `{}`", lockfile.command()))?;
+    if !output.status.success() {
+        return Err(miette!(
+            "Failed to refresh {}:\n{}",
+            lockfile.path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(Some(lockfile.path))
+}
+
+/// Path of the lockfile belonging to a manifest, relative to the work directory.
+pub fn lockfile_path(workdir: &Path, file: &VersionFile) -> Option<PathBuf> {
+    Lockfile::of(workdir, file).map(|l| l.path)
+}
+
+/// Describe the lockfile refresh for a dry run.
+pub fn describe_lockfile_refresh(workdir: &Path, file: &VersionFile) -> Option<String> {
+    Lockfile::of(workdir, file).map(|l| format!("{} (`{}`)", l.path.display(), l.command()))
+}
+
+fn set_toml_version(contents: &str, keys: &[&[&str]], version: &str) -> MietteResult<String> {
+    let mut doc: DocumentMut = contents.parse().into_diagnostic()?;
+
+    for key in keys {
+        let (last, tables) = key.split_last().expect("Keys are never empty");
+        // `Item::get_mut` would insert missing tables, look them up without changing the document
+        let mut item = Some(doc.as_item_mut());
+        for table in tables {
+            item = item
+                .and_then(|i| i.as_table_like_mut())
+                .and_then(|t| t.get_mut(table));
+        }
+        // Only a plain string is a version, `version.workspace = true` is inherited
+        if let Some(value) = item
+            .and_then(|i| i.as_table_like_mut())
+            .and_then(|t| t.get_mut(last))
+            && value.is_str()
+        {
+            let decor = value.as_value().map(|v| v.decor().clone());
+            *value = toml_edit::value(version);
+            if let (Some(decor), Some(value)) = (decor, value.as_value_mut()) {
+                *value.decor_mut() = decor;
+            }
+            return Ok(doc.to_string());
+        }
+    }
+
+    Err(miette!(
+        "No version found at {}",
+        keys.iter()
+            .map(|k| k.join("."))
+            .collect::<Vec<_>>()
+            .join(" or ")
+    ))
+}
+
+fn set_package_json_version(contents: &str, version: &str) -> MietteResult<String> {
+    // Parse to find the top level version, then replace it textually to keep formatting
+    let json: serde_json::Value = serde_json::from_str(contents).into_diagnostic()?;
+    let current = json
+        .get("version")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| miette!("No top level \"version\" field"))?;
+
+    let re = Regex::new(&format!(
+        r#""version"(\s*):(\s*)"{}""#,
+        regex::escape(current)
+    ))
+    .into_diagnostic()?;
+    // Dependencies may have the same version, only replace the one of the top level object
+    let field = re
+        .captures_iter(contents)
+        .find(|captures| json_depth(&contents[..captures.get_match().start()]) == 1)
+        .ok_or_else(|| miette!("No top level \"version\" field"))?;
+    let range = field.get_match().range();
+    let replacement = format!(r#""version"{}:{}"{version}""#, &field[1], &field[2]);
+    Ok(format!(
+        "{}{replacement}{}",
+        &contents[..range.start],
+        &contents[range.end..]
+    ))
+}
+
+/// Nesting depth of objects and arrays at the end of a JSON prefix.
+fn json_depth(prefix: &str) -> usize {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in prefix.chars() {
+        match (in_string, c) {
+            (true, _) if escaped => escaped = false,
+            (true, '\\') => escaped = true,
+            (true, '"') => in_string = false,
+            (false, '"') => in_string = true,
+            (false, '{' | '[') => depth += 1,
+            (false, '}' | ']') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn replace_pattern(contents: &str, pattern: &str, version: &str) -> MietteResult<String> {
+    let re = Regex::new(pattern)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid version pattern '{pattern}'"))?;
+    let captures = re
+        .captures(contents)
+        .ok_or_else(|| miette!("Pattern '{pattern}' did not match"))?;
+    let version_match = captures
+        .name("version")
+        .or_else(|| captures.get(1))
+        .ok_or_else(|| miette!("Pattern '{pattern}' has no `version` capture group"))?;
+
+    let mut new = String::with_capacity(contents.len());
+    new.push_str(&contents[..version_match.start()]);
+    new.push_str(version);
+    new.push_str(&contents[version_match.end()..]);
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> VersionFile {
+        VersionFile {
+            path: PathBuf::from(path),
+            pattern: None,
+        }
+    }
+
+    fn with_pattern(path: &str, pattern: &str) -> VersionFile {
+        VersionFile {
+            path: PathBuf::from(path),
+            pattern: Some(pattern.to_string()),
+        }
+    }
+
+    #[test]
+    fn cargo_toml() {
+        let cases = [
+            (
+                "[package]\nname = \"a\"\nversion = \"1.0.0\" # keep\n",
+                "[package]\nname = \"a\"\nversion = \"1.1.0\" # keep\n",
+            ),
+            (
+                "[workspace.package]\nversion = \"1.0.0\"\n",
+                "[workspace.package]\nversion = \"1.1.0\"\n",
+            ),
+            (
+                "[workspace]\nmembers = [\"a\"]\n\n[workspace.package]\nversion = \"1.0.0\"\n",
+                "[workspace]\nmembers = [\"a\"]\n\n[workspace.package]\nversion = \"1.1.0\"\n",
+            ),
+            // Dependency versions are left alone
+            (
+                "[package]\nversion = \"1.0.0\"\n\n[dependencies]\nserde = { version = \"1.0.0\" }\n",
+                "[package]\nversion = \"1.1.0\"\n\n[dependencies]\nserde = { version = \"1.0.0\" }\n",
+            ),
+        ];
+        for (old, new) in cases {
+            assert_eq!(set_version(&file("Cargo.toml"), old, "1.1.0").unwrap(), new);
+        }
+
+        // Inherited versions are set in the workspace manifest
+        let inherited = "[package]\nversion.workspace = true\n";
+        assert!(set_version(&file("crates/a/Cargo.toml"), inherited, "1.1.0").is_err());
+    }
+
+    #[test]
+    fn pyproject_toml() {
+        let cases = [
+            (
+                "[project]\nname = \"a\"\nversion = \"1.0.0\"\n",
+                "[project]\nname = \"a\"\nversion = \"1.1.0\"\n",
+            ),
+            (
+                "[tool.poetry]\nversion = \"1.0.0\"\n",
+                "[tool.poetry]\nversion = \"1.1.0\"\n",
+            ),
+        ];
+        for (old, new) in cases {
+            assert_eq!(
+                set_version(&file("pyproject.toml"), old, "1.1.0").unwrap(),
+                new
+            );
+        }
+        let dynamic = "[project]\ndynamic = [\"version\"]\n";
+        assert!(set_version(&file("pyproject.toml"), dynamic, "1.1.0").is_err());
+    }
+
+    #[test]
+    fn package_json() {
+        // Only the top level version changes, formatting is kept
+        let old = "{\n  \"dependencies\": { \"a\": { \"version\": \"1.0.0\" } },\n  \"version\" : \"1.0.0\"\n}\n";
+        let new = "{\n  \"dependencies\": { \"a\": { \"version\": \"1.0.0\" } },\n  \"version\" : \"1.1.0\"\n}\n";
+        assert_eq!(
+            set_version(&file("package.json"), old, "1.1.0").unwrap(),
+            new
+        );
+
+        // Brackets in strings do not count
+        let old = r#"{"description": "a \" } b", "version": "1.0.0"}"#;
+        let new = r#"{"description": "a \" } b", "version": "1.1.0"}"#;
+        assert_eq!(
+            set_version(&file("package.json"), old, "1.1.0").unwrap(),
+            new
+        );
+
+        assert!(set_version(&file("package.json"), "{\"name\": \"a\"}", "1.1.0").is_err());
+        assert!(set_version(&file("package.json"), "{", "1.1.0").is_err());
+    }
+
+    #[test]
+    fn pattern() {
+        let cases = [
+            (r#"VERSION = "(?<version>[^"]+)""#, "VERSION = \"1.1.0\"\n"),
+            (r#"VERSION = "([^"]+)""#, "VERSION = \"1.1.0\"\n"),
+        ];
+        for (pattern, new) in cases {
+            let version_file = with_pattern("src/version.py", pattern);
+            assert_eq!(
+                set_version(&version_file, "VERSION = \"1.0.0\"\n", "1.1.0").unwrap(),
+                new
+            );
+        }
+
+        // A pattern wins over the built-in handler
+        let version_file = with_pattern("Cargo.toml", r"# v(?<version>\S+)");
+        assert_eq!(
+            set_version(
+                &version_file,
+                "# v1.0.0\n[package]\nversion = \"1.0.0\"\n",
+                "1.1.0"
+            )
+            .unwrap(),
+            "# v1.1.0\n[package]\nversion = \"1.0.0\"\n"
+        );
+
+        for pattern in ["NOPE (?<version>.+)", "VERSION", "("] {
+            let version_file = with_pattern("src/version.py", pattern);
+            assert!(set_version(&version_file, "VERSION = \"1.0.0\"\n", "1.1.0").is_err());
+        }
+        assert!(set_version(&file("VERSION"), "1.0.0", "1.1.0").is_err());
+    }
+
+    #[test]
+    fn lockfile_of_workspace_member() {
+        let workdir = std::env::temp_dir().join(format!("tagge-lockfile-{}", std::process::id()));
+        let member = workdir.join("crates/a");
+        std::fs::create_dir_all(&member).unwrap();
+        std::fs::write(workdir.join("Cargo.lock"), "").unwrap();
+
+        let lockfile = Lockfile::of(&workdir, &file("crates/a/Cargo.toml")).unwrap();
+        assert_eq!(lockfile.path, Path::new("Cargo.lock"));
+        assert_eq!(lockfile.dir(), Path::new(""));
+
+        // The closest lockfile wins
+        std::fs::write(member.join("Cargo.lock"), "").unwrap();
+        let lockfile = Lockfile::of(&workdir, &file("crates/a/Cargo.toml")).unwrap();
+        assert_eq!(lockfile.path, Path::new("crates/a/Cargo.lock"));
+
+        assert!(Lockfile::of(&workdir, &file("crates/a/package.json")).is_none());
+        std::fs::remove_dir_all(&workdir).unwrap();
+    }
+}